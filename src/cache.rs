@@ -0,0 +1,131 @@
+//! 받은 이미지를 올리기 전까지 디스크에 저장해두는 곳
+//!
+//! 작품 번호, 페이지, 파일의 해시로 저장하기 때문에 올리다가 실패하거나 재시작해도 다시 받지 않고 저장해둔 이미지를 올림
+//!
+//! 올리고 나면 지움, 같은 이미지가 여러 작품에 있더라도 작품마다 따로 저장하기 때문에 다른 작품이 올리고 지워도 상관 없음
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use bytes::Bytes;
+use crawler::image::ImageKind;
+use tokio::fs;
+
+use crate::storage;
+
+/// 디스크에 저장해둔 이미지
+#[derive(Debug)]
+pub struct Staged {
+    path: PathBuf,
+    len: usize,
+}
+
+impl Staged {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub async fn read(&self) -> io::Result<Bytes> {
+        fs::read(&self.path).await.map(Bytes::from)
+    }
+
+    /// 올리고 나서 지움
+    pub async fn remove(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path).await {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// `{dir}/{id}_{page}_{hash}.{kind}`
+fn path(dir: &str, id: u32, page: usize, hash: &str, kind: ImageKind) -> PathBuf {
+    let kind = match kind {
+        ImageKind::Thumbnail => "thumbnail",
+        ImageKind::Original => "original",
+    };
+
+    Path::new(dir).join(format!("{id}_{page}_{hash}.{kind}"))
+}
+
+/// 저장해둔 이미지가 없다면 `None`
+pub async fn get(
+    dir: &str,
+    id: u32,
+    page: usize,
+    hash: &str,
+    kind: ImageKind,
+) -> io::Result<Option<Staged>> {
+    let path = path(dir, id, page, hash, kind);
+
+    match fs::metadata(&path).await {
+        Ok(x) if x.is_file() && x.len() > 0 => Ok(Some(Staged {
+            path,
+            len: x.len() as usize,
+        })),
+        Ok(_) => Ok(None),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+pub async fn put(
+    dir: &str,
+    id: u32,
+    page: usize,
+    hash: &str,
+    kind: ImageKind,
+    buf: &Bytes,
+) -> io::Result<Staged> {
+    fs::create_dir_all(dir).await?;
+
+    let path = path(dir, id, page, hash, kind);
+    let tmp = storage::with_suffix(&path, "tmp");
+
+    // 잃어버려도 다시 받으면 되기 때문에 fsync 하지 않음, 쓰다 만 파일을 읽지 않도록 다 쓴 다음에 옮김
+    fs::write(&tmp, buf).await?;
+    fs::rename(&tmp, &path).await?;
+
+    Ok(Staged {
+        path,
+        len: buf.len(),
+    })
+}
+
+/// `max_age`보다 오래된 이미지들을 지움 (올리지 못 하고 버려진 작품들)
+///
+/// 지운 파일의 수를 반환함
+pub async fn prune(dir: &str, max_age: Duration) -> io::Result<usize> {
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err),
+    };
+
+    let now = SystemTime::now();
+    let mut count = 0;
+
+    while let Some(entry) = entries.next_entry().await? {
+        // 읽는 도중에 올리고 지운 이미지는 건너뜀
+        let metadata = match entry.metadata().await {
+            Ok(x) => x,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+
+        if now.duration_since(metadata.modified()?).unwrap_or_default() <= max_age {
+            continue;
+        }
+
+        match fs::remove_file(entry.path()).await {
+            Ok(()) => count += 1,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(count)
+}
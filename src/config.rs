@@ -1,34 +1,1011 @@
-use std::{env, fmt::Debug, str::FromStr};
+use std::{
+    collections::HashMap, env, fs, io, net::SocketAddr, str::FromStr, sync::Arc, time::Duration,
+};
 
+use parking_lot::{RwLock, RwLockReadGuard};
 use sai::{Component, ComponentLifecycle};
+use serde::Deserialize;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::{mpsc, oneshot},
+};
 
-fn env<T>(key: &str) -> T
-where
-    T: FromStr,
-    <T as FromStr>::Err: Debug,
-{
-    let var = env::var(key).expect("Please set dotenv");
+use crate::retry::RetryPolicy;
+
+const CONFIG_FILE: &str = "sync.toml";
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}: {1}")]
+    Io(String, io::Error),
+
+    #[error("{0}: {1}")]
+    Toml(String, toml::de::Error),
+
+    #[error(".env: {0}")]
+    Dotenv(#[from] dotenvy::Error),
+
+    #[error("{0} is required")]
+    Missing(&'static str),
 
-    var.parse().expect("Please set dotenv to valid value")
+    #[error("{key}: {message}")]
+    Invalid { key: &'static str, message: String },
 }
 
+/// # Config
+///
+/// `CONFIG_FILE`(기본값 `sync.toml`), `.env`, 환경 변수 순서로 덮어씌워서 읽습니다.
+///
+/// 환경 변수의 이름은 키를 대문자로 바꾼 것 (`per_page` -> `PER_PAGE`)
+///
+/// ```toml
+/// per_page = 25
+/// poll_interval = 180
+/// empty_page_threshold = 3
+///
+/// library_url = "https://..."
+/// file_url = "https://..."
+/// auth_url = "https://..."
+///
+/// gallery_concurrency = 2
+/// large_gallery_pages = 100
+/// download_concurrency = 8
+/// download_concurrency_per_gallery = 4
+/// upload_concurrency = 8
+/// upload_concurrency_per_gallery = 4
+///
+/// id_queue_capacity = 128
+/// about_queue_capacity = 128
+/// sync_queue_capacity = 128
+/// progress_queue_capacity = 128
+/// error_queue_capacity = 128
+/// sync_queue_mib = 256
+///
+/// cache_dir = "cache/"
+/// cache_max_age = 168
+///
+/// source_rps = 10
+/// download_kib_per_sec = 0
+/// upload_kib_per_sec = 0
+/// library_rps = 0
+///
+/// health_interval = 10
+/// health_timeout = 5
+/// health_failure_threshold = 3
+///
+/// websocket_addr = "127.0.0.1:3000"
+/// metrics_addr = "127.0.0.1:9464"
+/// ```
+///
+/// SIGHUP을 받거나 웹소켓으로 `reload_config` command를 받으면 다시 읽음
+///
+/// 값이 올바르지 않다면 시작할 때는 종료하고, 다시 읽을 때는 이전 값을 그대로 사용함
+///
+/// `websocket_addr`, `metrics_addr`, `download_concurrency`, `*_queue_capacity`, `sync_queue_mib`는 재시작해야 적용됨
 #[derive(Component)]
 #[lifecycle]
 pub struct Config {
-    per_page: Option<usize>,
+    values: Option<Arc<RwLock<Values>>>,
+
+    tx: Option<mpsc::Sender<()>>,
+    rx: Option<oneshot::Receiver<()>>,
 }
 
 #[async_trait::async_trait]
 impl ComponentLifecycle for Config {
     async fn start(&mut self) {
-        dotenv::dotenv().ok();
+        let values = match Values::load() {
+            Ok(values) => values,
+            Err(err) => {
+                log::error!("config;{err}");
+                eprintln!("invalid config: {err}");
+                std::process::exit(1);
+            }
+        };
+
+        let values = Arc::new(RwLock::new(values));
+
+        self.values.replace(values.clone());
+
+        let (stop_sender, rx) = oneshot::channel();
+        let (tx, mut stop_receiver) = mpsc::channel(1);
+
+        self.tx.replace(tx);
+        self.rx.replace(rx);
+
+        let mut sighup = signal(SignalKind::hangup()).unwrap();
 
-        self.per_page.replace(env("PER_PAGE"));
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = stop_receiver.recv() => {
+                        break;
+                    }
+                    _ = sighup.recv() => {
+                        log::info!("config;sighup");
+
+                        if let Err(err) = reload(&values) {
+                            log::error!("config;reload;{err}");
+                        }
+                    }
+                };
+            }
+
+            log::debug!("shutdown_config");
+
+            stop_sender.send(()).unwrap();
+        });
+    }
+
+    async fn stop(&mut self) {
+        self.tx.take().unwrap().send(()).await.unwrap();
+
+        self.rx.take().unwrap().await.unwrap();
     }
 }
 
 impl Config {
+    /// 시스템을 시작하지 않고 설정만 읽음 (`sync login`)
+    ///
+    /// 다시 읽을 수 없음
+    pub fn load() -> Result<Self, Error> {
+        let values = Values::load()?;
+
+        Ok(Self {
+            values: Some(Arc::new(RwLock::new(values))),
+            tx: None,
+            rx: None,
+        })
+    }
+
+    /// 설정을 다시 읽음
+    ///
+    /// 올바르지 않은 값이 있다면 이전 값을 그대로 사용함
+    pub fn reload(&self) -> Result<(), Error> {
+        reload(self.values.as_ref().unwrap())
+    }
+
     pub fn per_page(&self) -> usize {
-        self.per_page.unwrap()
+        self.values().per_page
+    }
+
+    /// `Nozomi`가 작품 번호를 다 가져오고 다음 사이클까지 기다리는 시간
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.values().poll_interval)
+    }
+
+    /// `Nozomi`가 빈 페이지를 몇 번 연속으로 받으면 사이클을 끝낼지
+    pub fn empty_page_threshold(&self) -> usize {
+        self.values().empty_page_threshold
+    }
+
+    /// base url of madome library server
+    pub fn library_url(&self) -> String {
+        self.values().library_url.clone()
+    }
+
+    /// base url of madome file server
+    pub fn file_url(&self) -> String {
+        self.values().file_url.clone()
+    }
+
+    /// base url of madome auth server
+    pub fn auth_url(&self) -> String {
+        self.values().auth_url.clone()
+    }
+
+    /// 이미지를 올리기 전에 파일 서버에 이미 있는 페이지는 건너뜀
+    pub fn image_resume(&self) -> bool {
+        self.values().image_resume
+    }
+
+    /// `About`, `Image`에서 동시에 진행하는 작품 수
+    pub fn gallery_concurrency(&self) -> usize {
+        self.values().gallery_concurrency
+    }
+
+    /// release하기 전에 파일 서버에 모든 페이지와 썸네일이 있는지 확인함
+    pub fn release_verify(&self) -> bool {
+        self.values().release_verify
+    }
+
+    /// 페이지가 이보다 많으면 큰 작품으로 봄
+    ///
+    /// `Image`는 큰 작품을 `gallery_concurrency - 1`개까지만 동시에 진행해서 작은 작품이 기다리지 않도록 함
+    pub fn large_gallery_pages(&self) -> usize {
+        self.values().large_gallery_pages
+    }
+
+    /// 모든 작품을 합쳐서 동시에 받는 이미지 수
+    pub fn download_concurrency(&self) -> usize {
+        self.values().download_concurrency
+    }
+
+    /// 작품 하나에서 동시에 받는 이미지 수
+    pub fn download_concurrency_per_gallery(&self) -> usize {
+        self.values().download_concurrency_per_gallery
+    }
+
+    /// 모든 작품을 합쳐서 동시에 올리는 이미지 수
+    pub fn upload_concurrency(&self) -> usize {
+        self.values().upload_concurrency
+    }
+
+    /// 작품 하나에서 동시에 올리는 이미지 수
+    pub fn upload_concurrency_per_gallery(&self) -> usize {
+        self.values().upload_concurrency_per_gallery
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        let values = self.values();
+
+        RetryPolicy {
+            max_attempts: values.retry_max_attempts,
+            base_delay: Duration::from_millis(values.retry_base_delay),
+            max_delay: Duration::from_millis(values.retry_max_delay),
+        }
+    }
+
+    /// nozomi의 모든 페이지를 훑어서 마도메에 없는 작품을 찾음 (`Backfill`)
+    pub fn backfill(&self) -> bool {
+        self.values().backfill
+    }
+
+    /// `Backfill`이 페이지를 가져오는 간격
+    pub fn backfill_interval(&self) -> Duration {
+        Duration::from_millis(self.values().backfill_interval)
+    }
+
+    /// `Updater`가 오래된 작품이 있는지 확인하는 주기
+    pub fn update_interval(&self) -> Duration {
+        Duration::from_secs(self.values().update_interval)
+    }
+
+    /// 마지막으로 확인한 지 이만큼 지난 작품의 정보를 다시 확인함
+    pub fn update_after(&self) -> chrono::Duration {
+        chrono::Duration::days(self.values().update_after as i64)
+    }
+
+    /// `Updater`가 한 번에 확인하는 작품 수
+    pub fn update_batch(&self) -> usize {
+        self.values().update_batch
+    }
+
+    /// 실패한 작업들을 다시 시도할지 확인하는 주기
+    pub fn retry_queue_interval(&self) -> Duration {
+        Duration::from_secs(self.values().retry_queue_interval)
+    }
+
+    /// 이 시간동안 바뀌지 않은 작업은 멈춘 것으로 봄
+    ///
+    /// `Backfill`, `RetryQueue`는 진행 중인 작업이 없을 때만 진행하는데, 멈춘 작업은 진행 중인 것으로 보지 않음
+    pub fn job_timeout(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.values().job_timeout as i64)
+    }
+
+    /// 실패한 작품 하나를 최대 몇 번까지 다시 시도할지
+    pub fn retry_queue_max_attempts(&self) -> usize {
+        self.values().retry_queue_max_attempts
+    }
+
+    /// `Channel`의 채널마다 쌓아둘 수 있는 수
+    ///
+    /// (id, about, sync, progress, error)
+    pub fn queue_capacities(&self) -> [usize; 5] {
+        self.values().queue_capacities()
+    }
+
+    /// `about` 채널에 쌓아둘 수 있는 수
+    pub fn about_queue_capacity(&self) -> usize {
+        self.values().about_queue_capacity
+    }
+
+    /// `sync` 채널에 쌓아둘 수 있는 이미지의 크기 (bytes)
+    ///
+    /// 이미지는 `cache_dir`에 저장해두기 때문에 디스크를 얼마나 쓸지 정함
+    pub fn sync_queue_bytes(&self) -> usize {
+        self.values().sync_queue_mib * 1024 * 1024
+    }
+
+    /// 받은 이미지를 올리기 전까지 저장해두는 폴더
+    pub fn cache_dir(&self) -> String {
+        self.values().cache_dir.clone()
+    }
+
+    /// 이보다 오래된 이미지는 시작할 때 지움
+    pub fn cache_max_age(&self) -> Duration {
+        Duration::from_secs(self.values().cache_max_age * 3600)
+    }
+
+    /// 원본 사이트에 보내는 초당 요청 수 (0이라면 제한하지 않음)
+    pub fn source_rps(&self) -> u64 {
+        self.values().source_rps
+    }
+
+    /// 원본 사이트에서 초당 받는 bytes (0이라면 제한하지 않음)
+    pub fn download_bytes_per_sec(&self) -> u64 {
+        self.values().download_kib_per_sec * 1024
+    }
+
+    /// 파일 서버에 초당 올리는 bytes (0이라면 제한하지 않음)
+    pub fn upload_bytes_per_sec(&self) -> u64 {
+        self.values().upload_kib_per_sec * 1024
+    }
+
+    /// library 서버에 보내는 초당 요청 수 (0이라면 제한하지 않음)
+    pub fn library_rps(&self) -> u64 {
+        self.values().library_rps
+    }
+
+    /// `Health`가 마도메 서버들을 확인하는 주기
+    pub fn health_interval(&self) -> Duration {
+        Duration::from_secs(self.values().health_interval)
+    }
+
+    /// 이 시간 안에 응답하지 않으면 실패로 봄
+    pub fn health_timeout(&self) -> Duration {
+        Duration::from_secs(self.values().health_timeout)
+    }
+
+    /// 연속으로 몇 번 실패하면 작업을 멈출지
+    pub fn health_failure_threshold(&self) -> usize {
+        self.values().health_failure_threshold
+    }
+
+    /// `WebSocket`의 주소
+    ///
+    /// 기본값은 `127.0.0.1:3000`, 다른 곳에서 구독하려면 `0.0.0.0:3000`처럼 직접 설정해야 함
+    pub fn websocket_addr(&self) -> SocketAddr {
+        self.values().websocket_addr
+    }
+
+    /// prometheus가 가져가는 `/metrics`의 주소
+    ///
+    /// 인증 없이 보여주기 때문에 설정되어 있을 때만 열음
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.values().metrics_addr
+    }
+
+    /// 웹소켓으로 command를 보낼 때 같이 보내야함
+    ///
+    /// 설정되어 있지 않다면 command를 받지 않음
+    pub fn admin_token(&self) -> Option<String> {
+        self.values().admin_token.clone()
+    }
+
+    fn values(&self) -> RwLockReadGuard<'_, Values> {
+        self.values.as_ref().unwrap().read()
+    }
+}
+
+fn reload(values: &RwLock<Values>) -> Result<(), Error> {
+    let new = Values::load()?;
+
+    let mut values = values.write();
+
+    let restart = [
+        (
+            "websocket_addr",
+            values.websocket_addr != new.websocket_addr,
+        ),
+        ("metrics_addr", values.metrics_addr != new.metrics_addr),
+        (
+            "queue_capacity",
+            values.queue_capacities() != new.queue_capacities(),
+        ),
+        (
+            "sync_queue_mib",
+            values.sync_queue_mib != new.sync_queue_mib,
+        ),
+        (
+            "download_concurrency",
+            values.download_concurrency != new.download_concurrency,
+        ),
+    ];
+
+    for (key, changed) in restart {
+        if changed {
+            log::warn!("config;reload;{key};restart to apply");
+        }
+    }
+
+    log::info!("config;reload;changed={}", *values != new);
+
+    *values = new;
+
+    Ok(())
+}
+
+#[derive(PartialEq)]
+struct Values {
+    per_page: usize,
+    /// sec
+    poll_interval: u64,
+    empty_page_threshold: usize,
+
+    library_url: String,
+    file_url: String,
+    auth_url: String,
+
+    image_resume: bool,
+    release_verify: bool,
+
+    gallery_concurrency: usize,
+    large_gallery_pages: usize,
+    download_concurrency: usize,
+    download_concurrency_per_gallery: usize,
+    upload_concurrency: usize,
+    upload_concurrency_per_gallery: usize,
+
+    retry_max_attempts: usize,
+    /// ms
+    retry_base_delay: u64,
+    /// ms
+    retry_max_delay: u64,
+
+    /// sec
+    retry_queue_interval: u64,
+    retry_queue_max_attempts: usize,
+    /// min
+    job_timeout: u64,
+
+    backfill: bool,
+    /// ms
+    backfill_interval: u64,
+
+    /// sec
+    update_interval: u64,
+    /// day
+    update_after: u64,
+    update_batch: usize,
+
+    id_queue_capacity: usize,
+    about_queue_capacity: usize,
+    sync_queue_capacity: usize,
+    progress_queue_capacity: usize,
+    error_queue_capacity: usize,
+    sync_queue_mib: usize,
+
+    cache_dir: String,
+    /// hour
+    cache_max_age: u64,
+
+    source_rps: u64,
+    download_kib_per_sec: u64,
+    upload_kib_per_sec: u64,
+    library_rps: u64,
+
+    /// sec
+    health_interval: u64,
+    /// sec
+    health_timeout: u64,
+    health_failure_threshold: usize,
+
+    websocket_addr: SocketAddr,
+    metrics_addr: Option<SocketAddr>,
+    admin_token: Option<String>,
+}
+
+/// 설정 파일에는 일부만 적을 수 있음
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    per_page: Option<usize>,
+    poll_interval: Option<u64>,
+    empty_page_threshold: Option<usize>,
+
+    library_url: Option<String>,
+    file_url: Option<String>,
+    auth_url: Option<String>,
+
+    image_resume: Option<bool>,
+    release_verify: Option<bool>,
+
+    gallery_concurrency: Option<usize>,
+    large_gallery_pages: Option<usize>,
+    download_concurrency: Option<usize>,
+    download_concurrency_per_gallery: Option<usize>,
+    upload_concurrency: Option<usize>,
+    upload_concurrency_per_gallery: Option<usize>,
+
+    retry_max_attempts: Option<usize>,
+    retry_base_delay: Option<u64>,
+    retry_max_delay: Option<u64>,
+
+    retry_queue_interval: Option<u64>,
+    retry_queue_max_attempts: Option<usize>,
+    job_timeout: Option<u64>,
+
+    backfill: Option<bool>,
+    backfill_interval: Option<u64>,
+
+    update_interval: Option<u64>,
+    update_after: Option<u64>,
+    update_batch: Option<usize>,
+
+    id_queue_capacity: Option<usize>,
+    about_queue_capacity: Option<usize>,
+    sync_queue_capacity: Option<usize>,
+    progress_queue_capacity: Option<usize>,
+    error_queue_capacity: Option<usize>,
+    sync_queue_mib: Option<usize>,
+
+    cache_dir: Option<String>,
+    cache_max_age: Option<u64>,
+
+    source_rps: Option<u64>,
+    download_kib_per_sec: Option<u64>,
+    upload_kib_per_sec: Option<u64>,
+    library_rps: Option<u64>,
+
+    health_interval: Option<u64>,
+    health_timeout: Option<u64>,
+    health_failure_threshold: Option<usize>,
+
+    websocket_addr: Option<String>,
+    metrics_addr: Option<String>,
+    admin_token: Option<String>,
+}
+
+impl Values {
+    fn queue_capacities(&self) -> [usize; 5] {
+        [
+            self.id_queue_capacity,
+            self.about_queue_capacity,
+            self.sync_queue_capacity,
+            self.progress_queue_capacity,
+            self.error_queue_capacity,
+        ]
+    }
+
+    fn load() -> Result<Self, Error> {
+        let source = Source::load()?;
+        let file = source.file()?;
+
+        Self::read(&source, file)
+    }
+
+    fn read(source: &Source, file: File) -> Result<Self, Error> {
+        let values = Self {
+            per_page: source.get("per_page", file.per_page, None)?,
+            poll_interval: source.get("poll_interval", file.poll_interval, Some(180))?,
+            empty_page_threshold: source.get(
+                "empty_page_threshold",
+                file.empty_page_threshold,
+                Some(3),
+            )?,
+
+            library_url: source.get("library_url", file.library_url, None)?,
+            file_url: source.get("file_url", file.file_url, None)?,
+            auth_url: source.get("auth_url", file.auth_url, None)?,
+
+            image_resume: source.get("image_resume", file.image_resume, Some(true))?,
+            release_verify: source.get("release_verify", file.release_verify, Some(false))?,
+
+            gallery_concurrency: source.get(
+                "gallery_concurrency",
+                file.gallery_concurrency,
+                Some(2),
+            )?,
+            large_gallery_pages: source.get(
+                "large_gallery_pages",
+                file.large_gallery_pages,
+                Some(100),
+            )?,
+            download_concurrency: source.get(
+                "download_concurrency",
+                file.download_concurrency,
+                Some(8),
+            )?,
+            download_concurrency_per_gallery: source.get(
+                "download_concurrency_per_gallery",
+                file.download_concurrency_per_gallery,
+                Some(4),
+            )?,
+            upload_concurrency: source.get(
+                "upload_concurrency",
+                file.upload_concurrency,
+                Some(8),
+            )?,
+            upload_concurrency_per_gallery: source.get(
+                "upload_concurrency_per_gallery",
+                file.upload_concurrency_per_gallery,
+                Some(4),
+            )?,
+
+            retry_max_attempts: source.get(
+                "retry_max_attempts",
+                file.retry_max_attempts,
+                Some(3),
+            )?,
+            retry_base_delay: source.get("retry_base_delay", file.retry_base_delay, Some(1000))?,
+            retry_max_delay: source.get("retry_max_delay", file.retry_max_delay, Some(30000))?,
+
+            retry_queue_interval: source.get(
+                "retry_queue_interval",
+                file.retry_queue_interval,
+                Some(300),
+            )?,
+            retry_queue_max_attempts: source.get(
+                "retry_queue_max_attempts",
+                file.retry_queue_max_attempts,
+                Some(5),
+            )?,
+            job_timeout: source.get("job_timeout", file.job_timeout, Some(30))?,
+
+            backfill: source.get("backfill", file.backfill, Some(false))?,
+            backfill_interval: source.get(
+                "backfill_interval",
+                file.backfill_interval,
+                Some(5000),
+            )?,
+
+            update_interval: source.get("update_interval", file.update_interval, Some(3600))?,
+            update_after: source.get("update_after", file.update_after, Some(30))?,
+            update_batch: source.get("update_batch", file.update_batch, Some(100))?,
+
+            id_queue_capacity: source.get(
+                "id_queue_capacity",
+                file.id_queue_capacity,
+                Some(128),
+            )?,
+            about_queue_capacity: source.get(
+                "about_queue_capacity",
+                file.about_queue_capacity,
+                Some(128),
+            )?,
+            sync_queue_capacity: source.get(
+                "sync_queue_capacity",
+                file.sync_queue_capacity,
+                Some(128),
+            )?,
+            progress_queue_capacity: source.get(
+                "progress_queue_capacity",
+                file.progress_queue_capacity,
+                Some(128),
+            )?,
+            error_queue_capacity: source.get(
+                "error_queue_capacity",
+                file.error_queue_capacity,
+                Some(128),
+            )?,
+            sync_queue_mib: source.get("sync_queue_mib", file.sync_queue_mib, Some(256))?,
+
+            cache_dir: source.get("cache_dir", file.cache_dir, Some("cache/".to_string()))?,
+            cache_max_age: source.get("cache_max_age", file.cache_max_age, Some(168))?,
+
+            source_rps: source.get("source_rps", file.source_rps, Some(10))?,
+            download_kib_per_sec: source.get(
+                "download_kib_per_sec",
+                file.download_kib_per_sec,
+                Some(0),
+            )?,
+            upload_kib_per_sec: source.get(
+                "upload_kib_per_sec",
+                file.upload_kib_per_sec,
+                Some(0),
+            )?,
+            library_rps: source.get("library_rps", file.library_rps, Some(0))?,
+
+            health_interval: source.get("health_interval", file.health_interval, Some(10))?,
+            health_timeout: source.get("health_timeout", file.health_timeout, Some(5))?,
+            health_failure_threshold: source.get(
+                "health_failure_threshold",
+                file.health_failure_threshold,
+                Some(3),
+            )?,
+
+            websocket_addr: match source.var("websocket_addr").or(file.websocket_addr) {
+                Some(x) => parse("websocket_addr", &x)?,
+                None => SocketAddr::from(([127, 0, 0, 1], 3000)),
+            },
+            metrics_addr: match source
+                .var("metrics_addr")
+                .or(file.metrics_addr)
+                .filter(|x| !x.is_empty())
+            {
+                Some(x) => Some(parse("metrics_addr", &x)?),
+                None => None,
+            },
+            admin_token: source
+                .var("admin_token")
+                .or(file.admin_token)
+                .filter(|x| !x.is_empty()),
+        };
+
+        values.validate()?;
+
+        Ok(values)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let positive = [
+            ("per_page", self.per_page as u64),
+            ("poll_interval", self.poll_interval),
+            ("empty_page_threshold", self.empty_page_threshold as u64),
+            ("gallery_concurrency", self.gallery_concurrency as u64),
+            ("large_gallery_pages", self.large_gallery_pages as u64),
+            ("download_concurrency", self.download_concurrency as u64),
+            (
+                "download_concurrency_per_gallery",
+                self.download_concurrency_per_gallery as u64,
+            ),
+            ("upload_concurrency", self.upload_concurrency as u64),
+            (
+                "upload_concurrency_per_gallery",
+                self.upload_concurrency_per_gallery as u64,
+            ),
+            ("retry_max_attempts", self.retry_max_attempts as u64),
+            ("retry_queue_interval", self.retry_queue_interval),
+            ("job_timeout", self.job_timeout),
+            ("backfill_interval", self.backfill_interval),
+            ("update_interval", self.update_interval),
+            ("update_batch", self.update_batch as u64),
+            ("id_queue_capacity", self.id_queue_capacity as u64),
+            ("about_queue_capacity", self.about_queue_capacity as u64),
+            ("sync_queue_capacity", self.sync_queue_capacity as u64),
+            (
+                "progress_queue_capacity",
+                self.progress_queue_capacity as u64,
+            ),
+            ("error_queue_capacity", self.error_queue_capacity as u64),
+            ("sync_queue_mib", self.sync_queue_mib as u64),
+            ("cache_max_age", self.cache_max_age),
+            ("health_interval", self.health_interval),
+            ("health_timeout", self.health_timeout),
+            (
+                "health_failure_threshold",
+                self.health_failure_threshold as u64,
+            ),
+        ];
+
+        for (key, x) in positive {
+            if x == 0 {
+                return Err(invalid(key, "must be greater than 0"));
+            }
+        }
+
+        // `sync_queue_bytes`
+        if self.sync_queue_mib.checked_mul(1024 * 1024).is_none() {
+            return Err(invalid("sync_queue_mib", "is too large"));
+        }
+
+        if self.cache_dir.is_empty() {
+            return Err(invalid("cache_dir", "must not be empty"));
+        }
+
+        let urls = [
+            ("library_url", &self.library_url),
+            ("file_url", &self.file_url),
+            ("auth_url", &self.auth_url),
+        ];
+
+        for (key, x) in urls {
+            if !x.starts_with("http://") && !x.starts_with("https://") {
+                return Err(invalid(key, "must start with http:// or https://"));
+            }
+        }
+
+        if self.retry_base_delay > self.retry_max_delay {
+            return Err(invalid(
+                "retry_base_delay",
+                "must not be greater than retry_max_delay",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// 환경 변수와 `.env`
+///
+/// `dotenvy::dotenv()`는 이미 있는 환경 변수를 덮어쓰지 않기 때문에 다시 읽을 때 `.env`의 변경 사항을 알 수 없음
+struct Source {
+    dotenv: HashMap<String, String>,
+}
+
+impl Source {
+    // `from_path`와 `var`로는 바뀐 값을 다시 읽을 수 없음
+    fn load() -> Result<Self, Error> {
+        let dotenv = match dotenvy::from_path_iter(".env") {
+            Ok(iter) => iter.collect::<Result<_, _>>()?,
+            Err(err) if err.not_found() => HashMap::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(Self { dotenv })
+    }
+
+    fn var(&self, key: &str) -> Option<String> {
+        let key = key.to_uppercase();
+
+        env::var(&key)
+            .ok()
+            .or_else(|| self.dotenv.get(&key).cloned())
+    }
+
+    /// `CONFIG_FILE`을 따로 설정하지 않았다면 설정 파일이 없어도 됨
+    fn file(&self) -> Result<File, Error> {
+        let (path, required) = match self.var("config_file") {
+            Some(path) => (path, true),
+            None => (CONFIG_FILE.to_string(), false),
+        };
+
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(File::default())
+            }
+            Err(err) => return Err(Error::Io(path, err)),
+        };
+
+        toml::from_str(&content).map_err(|err| Error::Toml(path, err))
+    }
+
+    /// 환경 변수, 설정 파일, 기본값 순서
+    fn get<T>(&self, key: &'static str, file: Option<T>, default: Option<T>) -> Result<T, Error>
+    where
+        T: FromStr,
+        <T as FromStr>::Err: std::fmt::Display,
+    {
+        match self.var(key) {
+            Some(var) => parse(key, &var),
+            None => file.or(default).ok_or(Error::Missing(key)),
+        }
+    }
+}
+
+fn parse<T>(key: &'static str, var: &str) -> Result<T, Error>
+where
+    T: FromStr,
+    <T as FromStr>::Err: std::fmt::Display,
+{
+    var.parse()
+        .map_err(|err: <T as FromStr>::Err| invalid(key, format!("{var:?}: {err}")))
+}
+
+fn invalid(key: &'static str, message: impl Into<String>) -> Error {
+    Error::Invalid {
+        key,
+        message: message.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(dotenv: &[(&str, &str)]) -> Source {
+        Source {
+            dotenv: dotenv
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    fn values() -> Values {
+        let source = source(&[
+            ("PER_PAGE", "25"),
+            ("LIBRARY_URL", "http://localhost:8080"),
+            ("FILE_URL", "http://localhost:8081"),
+            ("AUTH_URL", "https://localhost:8082"),
+        ]);
+
+        Values::read(&source, File::default()).unwrap()
+    }
+
+    fn invalid_key(result: Result<(), Error>) -> &'static str {
+        match result {
+            Err(Error::Invalid { key, .. }) => key,
+            _ => panic!("not invalid"),
+        }
+    }
+
+    #[test]
+    fn env_over_dotenv() {
+        env::set_var("SYNC_TEST_ENV_OVER_DOTENV", "1");
+
+        let source = source(&[("SYNC_TEST_ENV_OVER_DOTENV", "2")]);
+
+        let x: u64 = source
+            .get("sync_test_env_over_dotenv", Some(3), Some(4))
+            .unwrap();
+        assert_eq!(x, 1);
+    }
+
+    #[test]
+    fn dotenv_over_file() {
+        let source = source(&[("SYNC_TEST_DOTENV_OVER_FILE", "2")]);
+
+        let x: u64 = source
+            .get("sync_test_dotenv_over_file", Some(3), Some(4))
+            .unwrap();
+        assert_eq!(x, 2);
+    }
+
+    #[test]
+    fn file_over_default() {
+        let source = source(&[]);
+
+        let x: u64 = source
+            .get("sync_test_file_over_default", Some(3), Some(4))
+            .unwrap();
+        assert_eq!(x, 3);
+
+        let x: u64 = source
+            .get("sync_test_file_over_default", None, Some(4))
+            .unwrap();
+        assert_eq!(x, 4);
+    }
+
+    #[test]
+    fn missing_and_unparsable() {
+        let source = source(&[("SYNC_TEST_UNPARSABLE", "ten")]);
+
+        let result = source.get::<u64>("sync_test_missing", None, None);
+        assert!(matches!(result, Err(Error::Missing("sync_test_missing"))));
+
+        // 잘못된 값은 설정 파일이나 기본값으로 넘어가지 않음
+        let result = source.get::<u64>("sync_test_unparsable", Some(3), Some(4));
+        assert!(matches!(
+            result,
+            Err(Error::Invalid {
+                key: "sync_test_unparsable",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        values().validate().unwrap();
+    }
+
+    #[test]
+    fn required() {
+        let source = source(&[("PER_PAGE", "25")]);
+
+        let result = Values::read(&source, File::default());
+        assert!(matches!(result, Err(Error::Missing("library_url"))));
+    }
+
+    #[test]
+    fn zero() {
+        let mut per_page = values();
+        per_page.per_page = 0;
+        assert_eq!(invalid_key(per_page.validate()), "per_page");
+
+        let mut sync_queue_mib = values();
+        sync_queue_mib.sync_queue_mib = 0;
+        assert_eq!(invalid_key(sync_queue_mib.validate()), "sync_queue_mib");
+    }
+
+    #[test]
+    fn sync_queue_mib_overflow() {
+        let mut values = values();
+        values.sync_queue_mib = usize::MAX / 1024;
+        assert_eq!(invalid_key(values.validate()), "sync_queue_mib");
+    }
+
+    #[test]
+    fn url_scheme() {
+        let mut values = values();
+        values.file_url = "localhost:8081".to_string();
+        assert_eq!(invalid_key(values.validate()), "file_url");
+    }
+
+    #[test]
+    fn retry_delay() {
+        let mut values = values();
+        values.retry_base_delay = values.retry_max_delay + 1;
+        assert_eq!(invalid_key(values.validate()), "retry_base_delay");
+    }
+
+    #[test]
+    fn empty_cache_dir() {
+        let mut values = values();
+        values.cache_dir = String::new();
+        assert_eq!(invalid_key(values.validate()), "cache_dir");
     }
 }
@@ -1,7 +1,15 @@
+use std::time::Instant;
+
+use futures::{stream::FuturesUnordered, StreamExt};
 use sai::{Component, ComponentLifecycle, Injected};
 use tokio::sync::{mpsc, oneshot};
 
-use crate::{container, SendError};
+use crate::{
+    config::Config,
+    container::{self, job::Stage},
+    retry::{retry, Retryable},
+    SendError,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -9,12 +17,38 @@ pub enum Error {
     Crawler(#[from] crawler::Error),
 }
 
+impl Retryable for Error {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Crawler(err) => err.is_retryable(),
+        }
+    }
+}
+
 #[derive(Component)]
 #[lifecycle]
 pub struct About {
+    #[injected]
+    config: Injected<Config>,
+
     #[injected]
     channel: Injected<container::Channel>,
 
+    #[injected]
+    token: Injected<container::Token>,
+
+    #[injected]
+    job: Injected<container::Job>,
+
+    #[injected]
+    control: Injected<container::Control>,
+
+    #[injected]
+    health: Injected<container::Health>,
+
+    #[injected]
+    rate_limit: Injected<container::RateLimit>,
+
     tx: Option<mpsc::Sender<()>>,
     rx: Option<oneshot::Receiver<()>>,
 }
@@ -28,31 +62,82 @@ impl ComponentLifecycle for About {
         self.tx.replace(tx);
         self.rx.replace(rx);
 
+        let config = self.config.clone();
         let channel = self.channel.clone();
+        let token = self.token.clone();
+        let job = self.job.clone();
+        let control = self.control.clone();
+        let health = self.health.clone();
+        let rate_limit = self.rate_limit.clone();
 
         tokio::spawn(async move {
+            // 작품 정보는 이미 올렸으니까 이미지부터 이어서 진행함
+            for x in job.unfinished().await {
+                if !matches!(x.stage, Stage::AboutSynced | Stage::Image) {
+                    continue;
+                }
+
+                let id = x.id;
+
+                log::info!("parse_about;resume;id={id}");
+
+                if let Some(about) = retry(config.retry_policy(), "parse_about", || {
+                    parse_gallery(&rate_limit, id)
+                })
+                .to(id, channel.err_tx())
+                .await
+                {
+                    channel
+                        .about_tx()
+                        .send(about)
+                        .await
+                        .expect("closed channel");
+                }
+            }
+
+            // 동시에 가져오고 있는 작품 정보들, `gallery_concurrency`를 넘지 않도록 받음
+            let mut running = FuturesUnordered::new();
+
             loop {
+                let can_receive = token.is_usable()
+                    && health.is_healthy()
+                    && running.len() < config.gallery_concurrency();
+
                 let id = tokio::select! {
                     _ = stop_receiver.recv() => {
                         break;
                     }
-                    id = channel.id_recv() => {
+                    Some(()) = running.next(), if !running.is_empty() => {
+                        continue;
+                    }
+                    // 토큰을 사용할 수 없다면 새로 발급 받을 때까지 받지 않음
+                    _ = token.wait_usable(), if !token.is_usable() => {
+                        continue;
+                    }
+                    // 마도메 서버가 응답하지 않는다면 응답할 때까지 받지 않음
+                    _ = health.wait_healthy(), if !health.is_healthy() => {
+                        continue;
+                    }
+                    id = channel.id_recv(), if can_receive => {
                         id
                     }
                 };
 
-                log::info!("parse_about;id={id}");
-
-                if let Some(about) = parse_gallery(id).to(id, channel.err_tx()).await {
-                    log::debug!("parse_about;send_about;id={id}");
-                    channel
-                        .sync_tx()
-                        .send(container::SyncKind::About(about))
-                        .await
-                        .expect("closed channel");
+                if control.is_blocked(id) {
+                    log::info!("parse_about;blocked;id={id}");
+                    continue;
                 }
+
+                running.push(process(&config, &channel, &rate_limit, id));
             }
 
+            // 작품 번호만 가져온 작품은 다음에 시작할 때 `Nozomi`에서 다시 보냄
+            if !running.is_empty() {
+                log::info!("parse_about;stop;in_progress={}", running.len());
+            }
+
+            drop(running);
+
             log::debug!("shutdown_about");
 
             stop_sender.send(()).unwrap();
@@ -66,7 +151,43 @@ impl ComponentLifecycle for About {
     }
 }
 
-async fn parse_gallery(id: u32) -> Result<crawler::model::Gallery, Error> {
+async fn process(
+    config: &Config,
+    channel: &container::Channel,
+    rate_limit: &container::RateLimit,
+    id: u32,
+) {
+    log::debug!("parse_about;id={id}");
+
+    let started = Instant::now();
+
+    if let Some(about) = retry(config.retry_policy(), "parse_about", || {
+        parse_gallery(rate_limit, id)
+    })
+    .to(id, channel.err_tx())
+    .await
+    {
+        channel.send_event(container::PipelineEvent::AboutParsed {
+            id,
+            total_page: about.files.len(),
+            elapsed_ms: started.elapsed().as_millis() as u64,
+        });
+
+        log::debug!("parse_about;send_about;id={id}");
+        channel
+            .sync_tx()
+            .send(container::SyncKind::About(about))
+            .await
+            .expect("closed channel");
+    }
+}
+
+pub(super) async fn parse_gallery(
+    rate_limit: &container::RateLimit,
+    id: u32,
+) -> Result<crawler::model::Gallery, Error> {
+    rate_limit.source().await;
+
     let r = crawler::gallery::parse(id).await?;
     Ok(r)
 }
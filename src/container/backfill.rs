@@ -0,0 +1,208 @@
+use std::io;
+
+use chrono::{DateTime, Utc};
+use sai::{Component, ComponentLifecycle, Injected};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    sync::{mpsc, oneshot},
+    time::sleep,
+};
+
+use crate::{
+    config::Config,
+    container::{self, nozomi::parse_not_contains, PipelineEvent},
+    storage, SendError,
+};
+
+const CURSOR_PATH: &str = "backfill/cursor.json";
+
+/// # Backfill
+///
+/// `Nozomi`는 최근 페이지만 보기 때문에 오래된 작품 중에 마도메에 없는 작품은 찾을 수 없습니다.
+///
+/// nozomi의 모든 페이지를 처음부터 끝까지 한 페이지씩 훑어서 마도메에 없는 작품을 보냅니다.
+///
+/// - 진행 중인 작업이 없을 때만 다음 페이지를 가져옴 (`Nozomi`보다 우선순위가 낮음)
+/// - 페이지를 가져올 때마다 `backfill_interval`만큼 기다림
+/// - 어디까지 훑었는지 `backfill/cursor.json`에 저장해서 재시작해도 이어서 진행함
+/// - 끝까지 훑었다면 멈춤, 다시 훑으려면 `backfill/cursor.json`을 지워야함
+#[derive(Component)]
+#[lifecycle]
+pub struct Backfill {
+    #[injected]
+    config: Injected<Config>,
+
+    #[injected]
+    channel: Injected<container::Channel>,
+
+    #[injected]
+    token: Injected<container::Token>,
+
+    #[injected]
+    job: Injected<container::Job>,
+
+    #[injected]
+    control: Injected<container::Control>,
+
+    #[injected]
+    health: Injected<container::Health>,
+
+    #[injected]
+    rate_limit: Injected<container::RateLimit>,
+
+    tx: Option<mpsc::Sender<()>>,
+    rx: Option<oneshot::Receiver<()>>,
+}
+
+#[async_trait::async_trait]
+impl ComponentLifecycle for Backfill {
+    async fn start(&mut self) {
+        let (stop_sender, rx) = oneshot::channel();
+        let (tx, mut stop_receiver) = mpsc::channel(1);
+
+        self.tx.replace(tx);
+        self.rx.replace(rx);
+
+        let config = self.config.clone();
+        let channel = self.channel.clone();
+        let token = self.token.clone();
+        let job = self.job.clone();
+        let control = self.control.clone();
+        let health = self.health.clone();
+        let rate_limit = self.rate_limit.clone();
+
+        tokio::spawn(async move {
+            let mut cursor = match Cursor::read().await {
+                Ok(cursor) => cursor,
+                Err(err) => {
+                    log::error!("Backfill: {err}");
+                    Cursor::default()
+                }
+            };
+
+            loop {
+                tokio::select! {
+                    _ = stop_receiver.recv() => {
+                        break;
+                    }
+                    _ = sleep(config.backfill_interval()) => {}
+                };
+
+                if !config.backfill() || cursor.done_at.is_some() {
+                    continue;
+                }
+
+                // 일시정지 중이거나 진행 중인 작업이 있다면 다음에 가져옴
+                if control.is_paused()
+                    || !token.is_usable()
+                    || !health.is_healthy()
+                    || job
+                        .in_progress(|id| control.is_blocked(id), config.job_timeout())
+                        .await
+                    || !channel.is_idle()
+                {
+                    continue;
+                }
+
+                let page = cursor.page + 1;
+
+                log::debug!("backfill;page={page}");
+
+                let ids =
+                    match parse_not_contains(&config, &token, &rate_limit, page, config.per_page())
+                        .to(None, channel.err_tx())
+                        .await
+                    {
+                        Some(ids) => ids,
+                        None => continue,
+                    };
+
+                match ids {
+                    Some(ids) => {
+                        for id in ids {
+                            if control.is_blocked(id) || job.get(id).await.is_some() {
+                                continue;
+                            }
+
+                            log::info!("backfill;id={id};page={page}");
+
+                            if job.discovered(id).to(id, channel.err_tx()).await.is_none() {
+                                continue;
+                            }
+
+                            channel.send_event(PipelineEvent::GalleryDiscovered { id });
+
+                            channel.id_tx().send(id).await.expect("closed id channel");
+                        }
+
+                        cursor.page = page;
+                    }
+                    None => {
+                        log::info!("backfill;done;page={}", cursor.page);
+
+                        cursor.done_at = Some(Utc::now());
+                    }
+                }
+
+                cursor.updated_at = Utc::now();
+
+                if let Err(err) = cursor.write().await {
+                    log::error!("Backfill: {err}");
+                }
+            }
+
+            log::debug!("shutdown_backfill");
+
+            stop_sender.send(()).unwrap();
+        });
+    }
+
+    async fn stop(&mut self) {
+        self.tx.take().unwrap().send(()).await.unwrap();
+
+        self.rx.take().unwrap().await.unwrap();
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    /// 마지막으로 훑은 페이지
+    page: usize,
+    /// 끝까지 훑은 시간
+    done_at: Option<DateTime<Utc>>,
+    updated_at: DateTime<Utc>,
+}
+
+impl Default for Cursor {
+    fn default() -> Self {
+        Self {
+            page: 0,
+            done_at: None,
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+impl Cursor {
+    /// 올바르지 않은 파일이라면 옮겨두고 처음부터 다시 훑음
+    async fn read() -> io::Result<Self> {
+        match storage::read_json(CURSOR_PATH).await {
+            Ok(cursor) => Ok(cursor.unwrap_or_default()),
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                log::error!("Backfill: {err}");
+
+                storage::quarantine(CURSOR_PATH).await?;
+
+                Ok(Self::default())
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn write(&self) -> io::Result<()> {
+        fs::create_dir_all("backfill/").await?;
+
+        storage::write_json(CURSOR_PATH, self).await
+    }
+}
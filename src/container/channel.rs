@@ -1,14 +1,43 @@
-use sai::{Component, ComponentLifecycle};
-use tokio::sync::{mpsc, Mutex};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 
-use crate::container;
+use sai::{Component, ComponentLifecycle, Injected};
+use serde::Serialize;
+use tokio::sync::{
+    broadcast,
+    mpsc::{self, error::TrySendError},
+    Mutex, OwnedSemaphorePermit, Semaphore,
+};
+
+use crate::{
+    cache::Staged,
+    config::Config,
+    container::{self, PipelineEvent},
+};
+
+/// `Config::queue_capacities`의 순서
+const QUEUES: [&str; 5] = ["id", "about", "sync", "progress", "error"];
+
+const ID: usize = 0;
+const ABOUT: usize = 1;
+const SYNC: usize = 2;
+const PROGRESS: usize = 3;
+const ERROR: usize = 4;
 
 /// id, page, total_page, error
-type ErrMsg = (Option<u32>, Option<usize>, Option<usize>, crate::Error);
+pub type ErrMsg = (Option<u32>, Option<usize>, Option<usize>, crate::Error);
 
+/// 채널마다 쌓아둘 수 있는 수는 `*_queue_capacity`
+///
+/// `sync` 채널은 쌓여있는 이미지의 크기도 `sync_queue_mib`를 넘지 않도록 `send_image`로 보내야함
 #[derive(Component)]
 #[lifecycle]
 pub struct Channel {
+    #[injected]
+    config: Injected<Config>,
+
     id_tx: Option<mpsc::Sender<u32>>,
     id_rx: Option<Mutex<mpsc::Receiver<u32>>>,
 
@@ -22,32 +51,66 @@ pub struct Channel {
     progress_rx: Option<Mutex<mpsc::Receiver<container::ProgressKind>>>,
 
     // id, page, error
-    err_tx: Option<mpsc::Sender<ErrMsg>>,
+    err_tx: Option<ErrorSender>,
     err_rx: Option<Mutex<mpsc::Receiver<ErrMsg>>>,
+
+    // 받는 쪽이 없어도 보내는 쪽이 막히지 않음
+    event_tx: Option<broadcast::Sender<PipelineEvent>>,
+
+    /// `sync` 채널에 쌓여있는 이미지의 크기만큼 잡아둠
+    sync_bytes: Option<Arc<Semaphore>>,
+    stats: Option<Stats>,
+}
+
+struct Stats {
+    capacities: [usize; 5],
+    high_water: [AtomicUsize; 5],
+    sync_bytes: usize,
+    sync_bytes_high_water: AtomicUsize,
 }
 
 #[async_trait::async_trait]
 impl ComponentLifecycle for Channel {
     async fn start(&mut self) {
-        let (tx, rx) = mpsc::channel(128);
+        let capacities = self.config.queue_capacities();
+
+        let (tx, rx) = mpsc::channel(capacities[ID]);
         self.id_tx.replace(tx);
         self.id_rx.replace(Mutex::new(rx));
 
-        let (tx, rx) = mpsc::channel(128);
+        let (tx, rx) = mpsc::channel(capacities[ABOUT]);
         self.about_tx.replace(tx);
         self.about_rx.replace(Mutex::new(rx));
 
-        let (tx, rx) = mpsc::channel(128);
+        let (tx, rx) = mpsc::channel(capacities[SYNC]);
         self.sync_tx.replace(tx);
         self.sync_rx.replace(Mutex::new(rx));
 
-        let (tx, rx) = mpsc::channel(128);
+        let (tx, rx) = mpsc::channel(capacities[PROGRESS]);
         self.progress_tx.replace(tx);
         self.progress_rx.replace(Mutex::new(rx));
 
-        let (tx, rx) = mpsc::channel(128);
-        self.err_tx.replace(tx);
+        let (tx, rx) = mpsc::channel(capacities[ERROR]);
+        self.err_tx.replace(ErrorSender {
+            tx,
+            overflow: Arc::new(AtomicUsize::new(0)),
+            closed: Arc::new(AtomicUsize::new(0)),
+        });
         self.err_rx.replace(Mutex::new(rx));
+
+        let (tx, _rx) = broadcast::channel(1024);
+        self.event_tx.replace(tx);
+
+        let sync_bytes = self.config.sync_queue_bytes();
+
+        self.sync_bytes
+            .replace(Arc::new(Semaphore::new(sync_bytes)));
+        self.stats.replace(Stats {
+            capacities,
+            high_water: Default::default(),
+            sync_bytes,
+            sync_bytes_high_water: AtomicUsize::new(0),
+        });
     }
 }
 
@@ -58,7 +121,10 @@ impl Channel {
 
     pub async fn id_recv(&self) -> u32 {
         let mut rx = self.id_rx.as_ref().unwrap().lock().await;
-        rx.recv().await.expect("closed channel")
+        let x = rx.recv().await.expect("closed channel");
+
+        self.record(ID);
+        x
     }
 
     pub fn about_tx(&self) -> mpsc::Sender<crawler::model::Gallery> {
@@ -67,16 +133,67 @@ impl Channel {
 
     pub async fn about_recv(&self) -> crawler::model::Gallery {
         let mut rx = self.about_rx.as_ref().unwrap().lock().await;
-        rx.recv().await.expect("closed channel")
+        let x = rx.recv().await.expect("closed channel");
+
+        self.record(ABOUT);
+        x
     }
 
+    /// 이미지는 `send_image`로 보내야함
     pub fn sync_tx(&self) -> mpsc::Sender<container::SyncKind> {
         self.sync_tx.clone().unwrap()
     }
 
+    /// `sync_queue_mib`에서 이미지의 크기만큼 잡아두고 보냄
+    ///
+    /// 잡아둔 크기는 `Sync`가 이미지를 올리고 나서 버리면 돌려줌
+    pub async fn send_image(
+        &self,
+        id: u32,
+        page: usize,
+        total_page: usize,
+        image: crawler::image::Image,
+        staged: Staged,
+    ) {
+        let stats = self.stats();
+        let semaphore = self.sync_bytes.as_ref().unwrap();
+
+        // `sync_queue_mib`보다 큰 이미지도 혼자서는 보낼 수 있도록 함
+        let n = staged
+            .len()
+            .clamp(1, stats.sync_bytes)
+            .min(u32::MAX as usize);
+
+        let permit = semaphore
+            .clone()
+            .acquire_many_owned(n as u32)
+            .await
+            .unwrap();
+
+        stats.sync_bytes_high_water.fetch_max(
+            stats.sync_bytes - semaphore.available_permits(),
+            Ordering::Relaxed,
+        );
+
+        let staged = StagedImage {
+            staged,
+            _permit: permit,
+        };
+
+        self.sync_tx()
+            .send(container::SyncKind::Image(
+                id, page, total_page, image, staged,
+            ))
+            .await
+            .expect("closed channel");
+    }
+
     pub async fn sync_recv(&self) -> container::SyncKind {
         let mut rx = self.sync_rx.as_ref().unwrap().lock().await;
-        rx.recv().await.expect("closed channel")
+        let x = rx.recv().await.expect("closed channel");
+
+        self.record(SYNC);
+        x
     }
 
     pub fn progress_tx(&self) -> mpsc::Sender<container::ProgressKind> {
@@ -85,15 +202,160 @@ impl Channel {
 
     pub async fn progress_recv(&self) -> container::ProgressKind {
         let mut rx = self.progress_rx.as_ref().unwrap().lock().await;
-        rx.recv().await.expect("closed channel")
+        let x = rx.recv().await.expect("closed channel");
+
+        self.record(PROGRESS);
+        x
+    }
+
+    /// id, about, sync 채널에 쌓여있는 게 없는지
+    pub fn is_idle(&self) -> bool {
+        [ID, ABOUT, SYNC].iter().all(|x| self.depth(*x) == 0)
+    }
+
+    /// 채널마다 쌓여있는 수와 가장 많이 쌓였던 수
+    ///
+    /// `sync_bytes`는 `sync` 채널에 쌓여있는 이미지의 크기 (bytes)
+    pub fn queue_stats(&self) -> Vec<QueueStats> {
+        let stats = self.stats();
+
+        let mut xs = QUEUES
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let depth = self.depth(i);
+
+                QueueStats {
+                    name,
+                    capacity: stats.capacities[i],
+                    depth,
+                    high_water: stats.high_water[i].load(Ordering::Relaxed).max(depth),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let depth = stats.sync_bytes - self.sync_bytes.as_ref().unwrap().available_permits();
+
+        xs.push(QueueStats {
+            name: "sync_bytes",
+            capacity: stats.sync_bytes,
+            depth,
+            high_water: stats
+                .sync_bytes_high_water
+                .load(Ordering::Relaxed)
+                .max(depth),
+        });
+
+        xs
     }
 
-    pub fn err_tx(&self) -> mpsc::Sender<ErrMsg> {
+    fn depth(&self, queue: usize) -> usize {
+        let available = match queue {
+            ID => self.id_tx.as_ref().unwrap().capacity(),
+            ABOUT => self.about_tx.as_ref().unwrap().capacity(),
+            SYNC => self.sync_tx.as_ref().unwrap().capacity(),
+            PROGRESS => self.progress_tx.as_ref().unwrap().capacity(),
+            _ => self.err_tx.as_ref().unwrap().tx.capacity(),
+        };
+
+        self.stats().capacities[queue] - available
+    }
+
+    /// 받을 때마다 받기 전에 쌓여있던 수로 가장 많이 쌓였던 수를 갱신함
+    ///
+    /// 쌓여있는 수는 받을 때만 줄어들기 때문에 받기 직전이 가장 많음
+    fn record(&self, queue: usize) {
+        let depth = self.depth(queue) + 1;
+
+        self.stats().high_water[queue].fetch_max(depth, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> &Stats {
+        self.stats.as_ref().unwrap()
+    }
+
+    pub fn err_tx(&self) -> ErrorSender {
         self.err_tx.clone().unwrap()
     }
 
+    /// (overflow, closed)
+    ///
+    /// 에러 채널이 가득 차거나 닫혀서 버려진 에러의 수
+    pub fn err_dropped(&self) -> (usize, usize) {
+        let tx = self.err_tx.as_ref().unwrap();
+
+        (
+            tx.overflow.load(Ordering::Relaxed),
+            tx.closed.load(Ordering::Relaxed),
+        )
+    }
+
     pub async fn err_recv(&self) -> ErrMsg {
         let mut rx = self.err_rx.as_ref().unwrap().lock().await;
-        rx.recv().await.expect("closed channel")
+        let x = rx.recv().await.expect("closed channel");
+
+        self.record(ERROR);
+        x
+    }
+
+    /// 받는 쪽이 없다면 버림
+    pub fn send_event(&self, event: PipelineEvent) {
+        let _r = self.event_tx.as_ref().unwrap().send(event);
+    }
+
+    pub fn subscribe_event(&self) -> broadcast::Receiver<PipelineEvent> {
+        self.event_tx.as_ref().unwrap().subscribe()
+    }
+}
+
+/// 에러를 보내는 쪽이 기다리지 않도록 `try_send`로 보냄
+///
+/// 에러 채널이 가득 찼다면 에러를 로그로만 남기고 버림
+#[derive(Clone)]
+pub struct ErrorSender {
+    tx: mpsc::Sender<ErrMsg>,
+    overflow: Arc<AtomicUsize>,
+    closed: Arc<AtomicUsize>,
+}
+
+impl ErrorSender {
+    pub fn send(&self, msg: ErrMsg) {
+        match self.tx.try_send(msg) {
+            Ok(()) => {}
+            Err(TrySendError::Full((id, page, _, err))) => {
+                let count = self.overflow.fetch_add(1, Ordering::Relaxed) + 1;
+
+                log::error!("error_channel;overflow={count};id={id:?};page={page:?};{err}");
+            }
+            Err(TrySendError::Closed((id, page, _, err))) => {
+                let count = self.closed.fetch_add(1, Ordering::Relaxed) + 1;
+
+                log::error!("error_channel;closed={count};id={id:?};page={page:?};{err}");
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStats {
+    pub name: &'static str,
+    pub capacity: usize,
+    pub depth: usize,
+    pub high_water: usize,
+}
+
+/// `send_image`로 보낸 이미지
+///
+/// 버리면 잡아둔 크기를 돌려줌, 디스크에 저장된 이미지는 그대로 남음
+pub struct StagedImage {
+    staged: Staged,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl std::ops::Deref for StagedImage {
+    type Target = Staged;
+
+    fn deref(&self) -> &Self::Target {
+        &self.staged
     }
 }
@@ -0,0 +1,166 @@
+use std::{
+    collections::HashSet,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use parking_lot::Mutex;
+use sai::{Component, ComponentLifecycle, Injected};
+use tokio::sync::{mpsc::error::TrySendError, watch};
+
+use crate::container::{self, PipelineEvent};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Job: {0}")]
+    Job(#[from] container::job::Error),
+
+    #[error("id queue is full, try again later")]
+    QueueFull,
+
+    #[error("id queue is closed")]
+    QueueClosed,
+}
+
+/// # Control
+///
+/// 실행 중인 인스턴스를 제어합니다. (`WebSocket`의 command)
+///
+/// - pause, resume: `Nozomi`가 작품 번호를 가져오는 걸 멈추거나 다시 시작함
+/// - abort: `Nozomi`의 현재 사이클에서 모은 작품 번호를 버리고 다음 사이클까지 기다림
+///   (진행 중인 사이클이 없다면 무시함)
+/// - cancel: 진행 중인 작품의 작업을 멈춤, 실패한 작업으로 남음
+/// - skip: 해당 작품은 더 이상 진행하지 않음
+/// - resync: 해당 작품을 처음부터 다시 진행함 (cancel, skip도 해제함)
+///
+/// cancel, skip은 메모리에만 저장하기 때문에 재시작하면 초기화됨
+#[derive(Component)]
+#[lifecycle]
+pub struct Control {
+    #[injected]
+    channel: Injected<container::Channel>,
+
+    #[injected]
+    job: Injected<container::Job>,
+
+    paused: Option<watch::Sender<bool>>,
+    aborted: Option<AtomicBool>,
+    state: Option<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    cancelled: HashSet<u32>,
+    skipped: HashSet<u32>,
+    /// 파일 서버에 있는 이미지도 다시 올림
+    forced: HashSet<u32>,
+}
+
+#[async_trait::async_trait]
+impl ComponentLifecycle for Control {
+    async fn start(&mut self) {
+        let (paused, _rx) = watch::channel(false);
+
+        self.paused.replace(paused);
+        self.aborted.replace(AtomicBool::new(false));
+        self.state.replace(Mutex::new(State::default()));
+    }
+}
+
+impl Control {
+    pub fn pause(&self) {
+        log::info!("control;pause");
+
+        self.paused.as_ref().unwrap().send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        log::info!("control;resume");
+
+        self.paused.as_ref().unwrap().send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.as_ref().unwrap().borrow()
+    }
+
+    /// 일시정지 중이라면 재개될 때까지 기다림
+    pub async fn wait_resumed(&self) {
+        let mut rx = self.paused.as_ref().unwrap().subscribe();
+
+        while *rx.borrow() {
+            if rx.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+
+    pub fn abort(&self) {
+        log::info!("control;abort");
+
+        self.aborted.as_ref().unwrap().store(true, Ordering::SeqCst);
+    }
+
+    /// abort 요청이 있었다면 `true`를 반환하고 초기화함
+    pub fn take_aborted(&self) -> bool {
+        self.aborted.as_ref().unwrap().swap(false, Ordering::SeqCst)
+    }
+
+    pub fn cancel(&self, id: u32) {
+        log::info!("control;cancel;id={id}");
+
+        self.state().cancelled.insert(id);
+    }
+
+    pub fn skip(&self, id: u32) {
+        log::info!("control;skip;id={id}");
+
+        self.state().skipped.insert(id);
+    }
+
+    /// 작업을 진행하면 안 되는 작품인지
+    pub fn is_blocked(&self, id: u32) -> bool {
+        let state = self.state();
+
+        state.cancelled.contains(&id) || state.skipped.contains(&id)
+    }
+
+    /// resync 요청이 있었다면 `true`를 반환하고 초기화함
+    pub fn take_forced(&self, id: u32) -> bool {
+        self.state().forced.remove(&id)
+    }
+
+    /// `WebSocket`에서 응답을 기다리지 않도록 `id` 채널이 가득 찼다면 아무것도 바꾸지 않고 에러를 반환함
+    pub async fn resync(&self, id: u32) -> Result<(), Error> {
+        log::info!("control;resync;id={id}");
+
+        let tx = self.channel.id_tx();
+
+        let permit = tx.try_reserve().map_err(|err| match err {
+            TrySendError::Full(()) => Error::QueueFull,
+            TrySendError::Closed(()) => Error::QueueClosed,
+        })?;
+
+        {
+            let mut state = self.state();
+
+            state.cancelled.remove(&id);
+            state.skipped.remove(&id);
+            state.forced.insert(id);
+        }
+
+        self.job.reset(id).await?;
+
+        permit.send(id);
+
+        Ok(())
+    }
+
+    /// `Image`, `Sync`에서 취소된 작품의 작업을 멈췄을 때
+    pub fn notify_cancelled(&self, id: u32) {
+        self.channel.send_event(PipelineEvent::Cancelled { id });
+    }
+
+    fn state(&self) -> parking_lot::MutexGuard<'_, State> {
+        self.state.as_ref().unwrap().lock()
+    }
+}
@@ -1,15 +1,17 @@
-use std::{io, path::Path};
+use std::io;
 
 use chrono::{DateTime, Utc};
 use sai::{Component, ComponentLifecycle, Injected};
 use serde::{Deserialize, Serialize};
 use tokio::{
-    fs::{self, File},
-    io::{AsyncReadExt, AsyncWriteExt},
+    fs,
     sync::{mpsc, oneshot},
 };
 
-use crate::{container, Error};
+use crate::{
+    container::{self, PipelineEvent},
+    storage, Error,
+};
 
 #[derive(Component)]
 #[lifecycle]
@@ -17,6 +19,9 @@ pub struct ErrorManager {
     #[injected]
     channel: Injected<container::Channel>,
 
+    #[injected]
+    job: Injected<container::Job>,
+
     tx: Option<mpsc::Sender<()>>,
     rx: Option<oneshot::Receiver<()>>,
 }
@@ -31,6 +36,7 @@ impl ComponentLifecycle for ErrorManager {
         self.rx.replace(rx);
 
         let channel = self.channel.clone();
+        let job = self.job.clone();
 
         tokio::spawn(async move {
             loop {
@@ -48,13 +54,11 @@ impl ComponentLifecycle for ErrorManager {
                     log::error!("{err}");
                 }
 
-                // TODO: 사용자에게 에러가 뭔지를 보여줄 거기 때문에
+                // 사용자에게 에러가 뭔지를 보여줄 거기 때문에
                 //
                 // 기본적으로는 실시간으로 웹소켓으로 쏴주고
                 // 클라이언트에서 어떤 작품이 release: false일 때 이것이 에러때문인지 아니면 진행 중인지 알 수가 없으니까
-                // 클라이언트에서 요청해서 특정 작품에 대한 에러메세지를 요구하면 있으면 json으로 주고 아니면 null로 줌
-                //
-                // 이거랑 관련해서 progress도 크롤러 웹소켓을 통해서 주는 게 나을 듯?
+                // 클라이언트에서 요청해서 특정 작품에 대한 에러메세지를 요구하면 있으면 json으로 주고 아니면 null로 줌 (`WebSocket`의 lookup)
                 let (id, page, total_page, err) = err_info;
 
                 let mut json = ErrorJson {
@@ -67,6 +71,11 @@ impl ComponentLifecycle for ErrorManager {
                 };
 
                 match err {
+                    // 썸네일은 0 페이지
+                    Error::Image(_) | Error::Sync(_) if page == Some(0) => {
+                        json.kind = ErrorKind::Thumbnail;
+                    }
+
                     Error::About(_err) => {
                         json.kind = ErrorKind::About;
                     }
@@ -86,13 +95,35 @@ impl ComponentLifecycle for ErrorManager {
                     Error::Image(_err) => {
                         json.kind = ErrorKind::Image;
                     }
+
+                    Error::Job(_err) => {
+                        json.kind = ErrorKind::Job;
+                    }
+
+                    Error::Health(_err) => {
+                        json.kind = ErrorKind::Health;
+                    }
+                }
+
+                if let Some(id) = id {
+                    if let Err(err) = job.failed(id).await {
+                        log::error!("ErrorManager: {err}");
+                    }
                 }
 
+                channel.send_event(PipelineEvent::Failed(json.clone()));
+
                 if let Err(err) = json.write_error_file().await {
                     log::error!("ErrorManager: {err}");
                 }
             }
 
+            let (overflow, closed) = channel.err_dropped();
+
+            if overflow + closed > 0 {
+                log::warn!("ErrorManager: dropped errors;overflow={overflow};closed={closed}");
+            }
+
             stop_sender.send(()).unwrap();
         });
     }
@@ -104,26 +135,34 @@ impl ComponentLifecycle for ErrorManager {
     }
 }
 
-#[derive(Serialize, Deserialize)]
-enum ErrorKind {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ErrorKind {
     About,
     Sync,
     Token,
     Nozomi,
     Image,
+    Thumbnail,
+    Job,
+    Health,
 }
 
-#[derive(Serialize, Deserialize)]
-struct ErrorJson {
-    id: Option<u32>,
-    page: Option<usize>,
-    total_page: Option<usize>,
-    kind: ErrorKind,
-    err: String,
-    created_at: DateTime<Utc>,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorJson {
+    pub(crate) id: Option<u32>,
+    pub(crate) page: Option<usize>,
+    pub(crate) total_page: Option<usize>,
+    pub(crate) kind: ErrorKind,
+    pub(crate) err: String,
+    pub(crate) created_at: DateTime<Utc>,
 }
 
 impl ErrorJson {
+    /// 에러 기록이 없다면 `None`
+    pub(super) async fn read(id: u32) -> io::Result<Option<Vec<Self>>> {
+        storage::read_json(format!("error/{id}.json")).await
+    }
+
     async fn write_error_file(self) -> io::Result<()> {
         fs::create_dir_all("error/").await?;
 
@@ -131,27 +170,57 @@ impl ErrorJson {
             Some(id) => format!("error/{id}.json"),
             None => "error/_.json".to_string(),
         };
-        let p: &Path = p.as_ref();
 
-        let mut xs = if p.exists() {
-            let mut file = File::open(p).await?;
-            let mut buf = Vec::new();
+        // 올바르지 않은 파일은 옮겨두고 새로 기록함
+        let mut xs = match storage::read_json::<Vec<Self>>(&p).await {
+            Ok(xs) => xs.unwrap_or_default(),
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                log::error!("ErrorManager: {err}");
 
-            file.read_to_end(&mut buf).await?;
+                storage::quarantine(&p).await?;
 
-            serde_json::from_slice::<Vec<Self>>(&buf).unwrap_or_default()
-        } else {
-            Vec::new()
+                Vec::new()
+            }
+            Err(err) => return Err(err),
         };
 
         xs.push(self);
 
-        let mut file = File::create(p).await?;
+        storage::write_json(&p, &xs).await
+    }
+}
+
+/// 에러 기록이 있는 작품들
+pub(super) async fn error_ids() -> io::Result<Vec<u32>> {
+    let mut ids = Vec::new();
+
+    let mut entries = match fs::read_dir("error/").await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(ids),
+        Err(err) => return Err(err),
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let id = name
+            .to_str()
+            .and_then(|x| x.strip_suffix(".json"))
+            .and_then(|x| x.parse().ok());
+
+        // `_.json`은 작품과 상관 없는 에러
+        if let Some(id) = id {
+            ids.push(id);
+        }
+    }
 
-        let r = serde_json::to_string(&xs).unwrap();
+    ids.sort_unstable();
 
-        file.write_all(r.as_bytes()).await?;
+    Ok(ids)
+}
 
-        Ok(())
+pub(super) async fn remove_error_file(id: u32) -> io::Result<()> {
+    match fs::remove_file(format!("error/{id}.json")).await {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
     }
 }
@@ -0,0 +1,204 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::container::{
+    error::ErrorJson,
+    health::{Circuit, Service},
+    token::TokenState,
+};
+
+/// 파이프라인에서 일어난 일들
+///
+/// `Channel`의 broadcast로 보내기 때문에 `WebSocket`, `Logger` 등이 따로 구독할 수 있음
+///
+/// 썸네일은 0 페이지, `elapsed_ms`는 해당 단계에 걸린 시간 (다시 시도한 시간 포함)
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PipelineEvent {
+    /// `Nozomi`가 작품 번호를 모으기 시작함
+    CycleStarted,
+
+    /// `Nozomi`가 모은 작품 번호를 다 보내고 다음 사이클까지 기다림
+    CycleFinished {
+        discovered: usize,
+        aborted: bool,
+        elapsed_ms: u64,
+    },
+
+    /// 마도메에 없는 작품을 찾음 (`Nozomi`, `Backfill`)
+    GalleryDiscovered {
+        id: u32,
+    },
+
+    AboutParsed {
+        id: u32,
+        total_page: usize,
+        elapsed_ms: u64,
+    },
+
+    /// 작품 정보를 library 서버에 올림
+    AboutSynced {
+        id: u32,
+        total_page: usize,
+        elapsed_ms: u64,
+    },
+
+    PageDownloaded {
+        id: u32,
+        page: usize,
+        total_page: usize,
+        bytes: usize,
+        elapsed_ms: u64,
+    },
+
+    /// 이미지를 파일 서버에 올림
+    PageUploaded {
+        id: u32,
+        page: usize,
+        total_page: usize,
+        bytes: usize,
+        elapsed_ms: u64,
+    },
+
+    /// 올린 페이지 수가 바뀜
+    Progress {
+        id: u32,
+        page: usize,
+        count: usize,
+        total_page: usize,
+    },
+
+    Released {
+        id: u32,
+        elapsed_ms: u64,
+    },
+
+    /// `ErrorManager`가 기록한 에러
+    Failed(ErrorJson),
+
+    /// `Control`로 작품의 작업을 멈춤
+    Cancelled {
+        id: u32,
+    },
+
+    /// 토큰의 상태가 바뀜
+    ///
+    /// `expired`라면 `sync login`으로 새로 발급할 때까지 모든 작업을 멈춤
+    Token {
+        state: TokenState,
+    },
+
+    /// 토큰을 갱신함
+    TokenRefreshed {
+        ok: bool,
+    },
+
+    /// 마도메 서버들의 상태가 바뀜
+    ///
+    /// `open`이라면 모든 서버가 다시 응답할 때까지 작업을 멈춤
+    Health {
+        circuit: Circuit,
+        failed: Vec<Service>,
+    },
+}
+
+impl PipelineEvent {
+    /// 작품과 상관 없는 이벤트라면 `None`
+    pub fn id(&self) -> Option<u32> {
+        match self {
+            Self::GalleryDiscovered { id }
+            | Self::AboutParsed { id, .. }
+            | Self::AboutSynced { id, .. }
+            | Self::PageDownloaded { id, .. }
+            | Self::PageUploaded { id, .. }
+            | Self::Progress { id, .. }
+            | Self::Released { id, .. }
+            | Self::Cancelled { id } => Some(*id),
+            Self::Failed(x) => x.id,
+            Self::CycleStarted
+            | Self::CycleFinished { .. }
+            | Self::Token { .. }
+            | Self::TokenRefreshed { .. }
+            | Self::Health { .. } => None,
+        }
+    }
+}
+
+/// 로그에 남기는 형식
+impl fmt::Display for PipelineEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CycleStarted => write!(f, "cycle_started"),
+            Self::CycleFinished {
+                discovered,
+                aborted,
+                elapsed_ms,
+            } => write!(
+                f,
+                "cycle_finished;discovered={discovered};aborted={aborted};elapsed={elapsed_ms}ms"
+            ),
+            Self::GalleryDiscovered { id } => write!(f, "gallery_discovered;id={id}"),
+            Self::AboutParsed {
+                id,
+                total_page,
+                elapsed_ms,
+            } => write!(
+                f,
+                "about_parsed;id={id};total_page={total_page};elapsed={elapsed_ms}ms"
+            ),
+            Self::AboutSynced {
+                id,
+                total_page,
+                elapsed_ms,
+            } => write!(
+                f,
+                "about_synced;id={id};total_page={total_page};elapsed={elapsed_ms}ms"
+            ),
+            Self::PageDownloaded {
+                id,
+                page,
+                total_page,
+                bytes,
+                elapsed_ms,
+            } => write!(
+                f,
+                "page_downloaded;id={id};page={page}/{total_page};bytes={bytes};elapsed={elapsed_ms}ms"
+            ),
+            Self::PageUploaded {
+                id,
+                page,
+                total_page,
+                bytes,
+                elapsed_ms,
+            } => write!(
+                f,
+                "page_uploaded;id={id};page={page}/{total_page};bytes={bytes};elapsed={elapsed_ms}ms"
+            ),
+            Self::Progress {
+                id,
+                count,
+                total_page,
+                ..
+            } => write!(
+                f,
+                "progress;id={id};count={count}/{total_page};{:.2}%",
+                *count as f32 / *total_page as f32 * 100.0
+            ),
+            Self::Released { id, elapsed_ms } => {
+                write!(f, "released;id={id};elapsed={elapsed_ms}ms")
+            }
+            Self::Failed(x) => write!(
+                f,
+                "failed;id={:?};page={:?};kind={:?};{}",
+                x.id, x.page, x.kind, x.err
+            ),
+            Self::Cancelled { id } => write!(f, "cancelled;id={id}"),
+            Self::Token { state } => write!(f, "token;state={state:?}"),
+            Self::TokenRefreshed { ok } => write!(f, "token_refreshed;ok={ok}"),
+            Self::Health { circuit, failed } => {
+                write!(f, "health;circuit={circuit:?};failed={failed:?}")
+            }
+        }
+    }
+}
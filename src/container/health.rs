@@ -0,0 +1,282 @@
+use std::{future::Future, io, sync::Arc, time::Duration};
+
+use madome_sdk::api::{file, library};
+use sai::{Component, ComponentLifecycle, Injected};
+use serde::Serialize;
+use tokio::{
+    net::TcpStream,
+    sync::{mpsc, oneshot, watch},
+    time::{sleep, timeout},
+};
+
+use crate::{
+    config::Config,
+    container::{self, PipelineEvent},
+    retry::Retryable,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Library Sdk: {0}")]
+    LibrarySdk(#[from] library::Error),
+
+    #[error("File Sdk: {0}")]
+    FileSdk(#[from] file::Error),
+
+    #[error("Auth: {0}")]
+    Auth(#[from] io::Error),
+
+    #[error("Timed out")]
+    Timeout,
+
+    #[error("Unhealthy services: {0:?}")]
+    Unhealthy(Vec<Service>),
+}
+
+impl Retryable for Error {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::LibrarySdk(err) => err.is_retryable(),
+            Self::FileSdk(err) => err.is_retryable(),
+            Self::Auth(_) | Self::Timeout => true,
+            Self::Unhealthy(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Service {
+    Library,
+    File,
+    Auth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Circuit {
+    /// 작업을 진행함
+    Closed,
+    /// 마도메 서버가 응답하지 않아서 작업을 멈춤
+    Open,
+}
+
+/// # Health
+///
+/// 주기적으로 마도메의 library, file, auth 서버에 요청을 보내서 응답하는지 확인합니다.
+///
+/// `health_failure_threshold`번 연속으로 실패하면 circuit을 열어서 작업을 멈추고,
+/// 다시 모든 서버가 응답하면 circuit을 닫아서 작업을 이어서 진행합니다.
+///
+/// 장애 중에 작업마다 에러 파일을 남기는 대신 에러를 한 번만 남김
+///
+/// - `Nozomi`, `About`, `Image`, `Sync`는 circuit이 닫힐 때까지 새 작업을 받지 않음
+/// - `Image`, `Sync`에서 진행 중인 작품도 페이지를 받거나 올리기 전에 circuit이 닫힐 때까지 기다림
+/// - `Backfill`, `RetryQueue`, `Updater`는 circuit이 열려 있다면 건너뜀
+///
+/// 서버가 응답하기만 하면 성공으로 봄 (네트워크 에러, 타임아웃, 429, 5xx만 실패)
+#[derive(Component)]
+#[lifecycle]
+pub struct Health {
+    #[injected]
+    config: Injected<Config>,
+
+    #[injected]
+    channel: Injected<container::Channel>,
+
+    #[injected]
+    token: Injected<container::Token>,
+
+    circuit: Option<Arc<watch::Sender<Circuit>>>,
+
+    tx: Option<mpsc::Sender<()>>,
+    rx: Option<oneshot::Receiver<()>>,
+}
+
+#[async_trait::async_trait]
+impl ComponentLifecycle for Health {
+    async fn start(&mut self) {
+        let (stop_sender, rx) = oneshot::channel();
+        let (tx, mut stop_receiver) = mpsc::channel(1);
+
+        self.tx.replace(tx);
+        self.rx.replace(rx);
+
+        let (circuit, _rx) = watch::channel(Circuit::Closed);
+        let circuit = Arc::new(circuit);
+
+        self.circuit.replace(circuit.clone());
+
+        let config = self.config.clone();
+        let channel = self.channel.clone();
+        let token = self.token.clone();
+
+        tokio::spawn(async move {
+            let mut failures = 0;
+
+            loop {
+                let failed = check(&config, &token).await;
+
+                if failed.is_empty() {
+                    failures = 0;
+                } else {
+                    failures += 1;
+
+                    for (service, err) in &failed {
+                        log::warn!("health;service={service:?};failures={failures};{err}");
+                    }
+                }
+
+                let next = if failed.is_empty() {
+                    Circuit::Closed
+                } else if failures >= config.health_failure_threshold() {
+                    Circuit::Open
+                } else {
+                    *circuit.borrow()
+                };
+
+                if circuit.send_replace(next) != next {
+                    let failed = failed.into_iter().map(|(x, _)| x).collect::<Vec<_>>();
+
+                    channel.send_event(PipelineEvent::Health {
+                        circuit: next,
+                        failed: failed.clone(),
+                    });
+
+                    if next == Circuit::Open {
+                        channel
+                            .err_tx()
+                            .send((None, None, None, Error::Unhealthy(failed).into()));
+                    }
+                }
+
+                tokio::select! {
+                    _ = stop_receiver.recv() => {
+                        break;
+                    }
+                    _ = sleep(config.health_interval()) => {}
+                };
+            }
+
+            log::debug!("shutdown_health");
+
+            stop_sender.send(()).unwrap();
+        });
+    }
+
+    async fn stop(&mut self) {
+        self.tx.take().unwrap().send(()).await.unwrap();
+
+        self.rx.take().unwrap().await.unwrap();
+    }
+}
+
+impl Health {
+    pub fn circuit(&self) -> Circuit {
+        *self.circuit.as_ref().unwrap().borrow()
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.circuit() == Circuit::Closed
+    }
+
+    /// circuit이 열려 있다면 닫힐 때까지 기다림
+    pub async fn wait_healthy(&self) {
+        let mut rx = self.circuit.as_ref().unwrap().subscribe();
+
+        while *rx.borrow() == Circuit::Open {
+            if rx.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// circuit이 닫혀 있다면 열릴 때까지 기다림
+    pub async fn wait_unhealthy(&self) {
+        let mut rx = self.circuit.as_ref().unwrap().subscribe();
+
+        while *rx.borrow() == Circuit::Closed {
+            if rx.changed().await.is_err() {
+                // 더 이상 바뀌지 않음
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// 응답하지 않은 서버들
+async fn check(config: &Config, token: &container::Token) -> Vec<(Service, Error)> {
+    let limit = config.health_timeout();
+
+    let (library, file, auth) = tokio::join!(
+        ping(limit, ping_library(config, token)),
+        ping(limit, ping_file(config, token)),
+        ping(limit, ping_auth(config)),
+    );
+
+    [
+        (Service::Library, library),
+        (Service::File, file),
+        (Service::Auth, auth),
+    ]
+    .into_iter()
+    .filter_map(|(service, r)| r.err().map(|err| (service, err)))
+    .collect()
+}
+
+async fn ping(limit: Duration, fut: impl Future<Output = Result<(), Error>>) -> Result<(), Error> {
+    match timeout(limit, fut).await {
+        Ok(Ok(())) => Ok(()),
+        // 서버가 응답했다면 성공으로 봄 (404, 401 등)
+        Ok(Err(err)) if !err.is_retryable() => Ok(()),
+        Ok(Err(err)) => Err(err),
+        Err(_elapsed) => Err(Error::Timeout),
+    }
+}
+
+#[allow(clippy::await_holding_lock)]
+async fn ping_library(config: &Config, token: &container::Token) -> Result<(), Error> {
+    let (_lock, token) = token.as_behavior();
+
+    library::get_books_by_ids(&config.library_url(), token, vec![]).await?;
+
+    Ok(())
+}
+
+#[allow(clippy::await_holding_lock)]
+async fn ping_file(config: &Config, token: &container::Token) -> Result<(), Error> {
+    let (_lock, token) = token.as_behavior();
+
+    file::list(&config.file_url(), token, "image/library/0/".to_string()).await?;
+
+    Ok(())
+}
+
+/// auth 서버는 토큰을 바꾸지 않는 요청이 없어서 연결만 확인함
+async fn ping_auth(config: &Config) -> Result<(), Error> {
+    let addr = authority(&config.auth_url()).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "invalid auth_url".to_string())
+    })?;
+
+    TcpStream::connect(addr).await?;
+
+    Ok(())
+}
+
+/// `https://example.com/a` -> `example.com:443`
+fn authority(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let host = rest.split('/').next().filter(|x| !x.is_empty())?;
+
+    if host.contains(':') {
+        return Some(host.to_string());
+    }
+
+    let port = match scheme {
+        "http" => 80,
+        "https" => 443,
+        _ => return None,
+    };
+
+    Some(format!("{host}:{port}"))
+}
@@ -1,21 +1,76 @@
-use bytes::Bytes;
+use std::{
+    collections::{BTreeSet, VecDeque},
+    time::Instant,
+};
+
+use futures::{
+    stream::{self, FuturesUnordered},
+    FutureExt, StreamExt,
+};
+use madome_sdk::api::file;
 use sai::{Component, ComponentLifecycle, Injected};
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, Semaphore};
 
-use crate::{container, SendError};
+use crate::{
+    cache::{self, Staged},
+    config::Config,
+    container::{self, ProgressKind},
+    retry::{retry, Retryable},
+    SendError,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Crawler: {0}")]
     Crawler(#[from] crawler::Error),
+
+    #[error("File Sdk: {0}")]
+    FileSdk(#[from] file::Error),
+
+    #[error("Job: {0}")]
+    Job(#[from] container::job::Error),
+
+    #[error("Cache: {0}")]
+    Cache(#[from] std::io::Error),
+
+    #[error("No pages")]
+    NoPages,
+}
+
+impl Retryable for Error {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Crawler(err) => err.is_retryable(),
+            Self::FileSdk(err) => err.is_retryable(),
+            Self::Job(_) | Self::Cache(_) | Self::NoPages => false,
+        }
+    }
 }
 
 #[derive(Component)]
 #[lifecycle]
 pub struct Image {
+    #[injected]
+    config: Injected<Config>,
+
     #[injected]
     channel: Injected<container::Channel>,
 
+    #[injected]
+    token: Injected<container::Token>,
+
+    #[injected]
+    job: Injected<container::Job>,
+
+    #[injected]
+    control: Injected<container::Control>,
+
+    #[injected]
+    health: Injected<container::Health>,
+
+    #[injected]
+    rate_limit: Injected<container::RateLimit>,
+
     tx: Option<mpsc::Sender<()>>,
     rx: Option<oneshot::Receiver<()>>,
 }
@@ -29,46 +84,135 @@ impl ComponentLifecycle for Image {
         self.tx.replace(tx);
         self.rx.replace(rx);
 
+        let config = self.config.clone();
         let channel = self.channel.clone();
+        let token = self.token.clone();
+        let job = self.job.clone();
+        let control = self.control.clone();
+        let health = self.health.clone();
+        let rate_limit = self.rate_limit.clone();
+
+        let downloads = Semaphore::new(self.config.download_concurrency());
 
         tokio::spawn(async move {
+            // 올리지 못 하고 버려진 이미지들을 지움
+            match cache::prune(&config.cache_dir(), config.cache_max_age()).await {
+                Ok(0) => {}
+                Ok(count) => log::info!("image;cache_pruned={count}"),
+                Err(err) => log::error!("Image: {err}"),
+            }
+
             // stop_receiver를 비동기로 받아야 하는 이유
             // 동기식으로 받게 되면 한 번 훑고 기다리지 않기 때문에 멈추는 데 오랜 시간이 걸릴 수도 있다
             // 그리고 멈추지 않을 수도 있는데, 아래 코드와 같이 about_recv에서 값을 받지 못 하면 영영 멈추지 못 함
             // tokio::select! { _ = stop_recv => break, r = about_recv => r }
+
+            // 동시에 진행 중인 작품들, `gallery_concurrency`를 넘지 않도록 받음
+            let mut running = FuturesUnordered::new();
+            // 진행 중인 큰 작품 수 (`large_gallery_pages`)
+            //
+            // 큰 작품은 `gallery_concurrency - 1`개까지만 진행해서 남은 자리로 작은 작품들을 진행함
+            let mut large = 0;
+            // 자리가 없어서 기다리는 큰 작품들, 들어온 순서대로 진행함
+            //
+            // 작품 정보만 가지고 있기 때문에 `about_queue_capacity`만큼 받아둠
+            let mut deferred = VecDeque::<crawler::model::Gallery>::new();
+
+            let (config, channel, token, job, control, health, rate_limit, downloads) = (
+                &config,
+                &channel,
+                &token,
+                &job,
+                &control,
+                &health,
+                &rate_limit,
+                &downloads,
+            );
+
+            let start = |about: crawler::model::Gallery, is_large: bool| {
+                process(
+                    config, channel, token, job, control, health, rate_limit, downloads, about,
+                )
+                .map(move |()| is_large)
+            };
+
             loop {
+                // `gallery_concurrency`가 1이라면 큰 작품도 하나씩 진행함
+                let max_large = config.gallery_concurrency().saturating_sub(1).max(1);
+
+                let can_run = token.is_usable()
+                    && health.is_healthy()
+                    && running.len() < config.gallery_concurrency();
+
+                if can_run && large < max_large {
+                    if let Some(about) = deferred.pop_front() {
+                        if control.is_blocked(about.id) {
+                            log::info!("image;blocked;id={}", about.id);
+                        } else {
+                            large += 1;
+                            running.push(start(about, true));
+                        }
+                        continue;
+                    }
+                }
+
+                // 미뤄둔 큰 작품은 `about_queue_capacity`만큼만 쌓아둠
+                let can_receive = can_run && deferred.len() < config.about_queue_capacity();
+
                 let about = tokio::select! {
                     _ = stop_receiver.recv() => {
                         break;
                     }
-                    about = channel.about_recv() => {
+                    Some(is_large) = running.next(), if !running.is_empty() => {
+                        if is_large {
+                            large -= 1;
+                        }
+                        continue;
+                    }
+                    // 토큰을 사용할 수 없다면 새로 발급 받을 때까지 받지 않음
+                    _ = token.wait_usable(), if !token.is_usable() => {
+                        continue;
+                    }
+                    // 마도메 서버가 응답하지 않는다면 응답할 때까지 받지 않음
+                    _ = health.wait_healthy(), if !health.is_healthy() => {
+                        continue;
+                    }
+                    about = channel.about_recv(), if can_receive => {
                         about
                     }
                 };
 
-                let total_page = about.files.len();
-
-                'b: for (page, file) in about.files.iter().enumerate().map(|(i, f)| (i + 1, f)) {
-                    match download_image(about.id, file, crawler::image::ImageKind::Original)
-                        .too(about.id, page, total_page, channel.err_tx())
-                        .await
-                    {
-                        Some((image, buf)) => {
-                            let _r = channel
-                                .sync_tx()
-                                .send(container::SyncKind::Image(
-                                    about.id, page, total_page, image, buf,
-                                ))
-                                .await
-                                .unwrap();
-                        }
-                        None => {
-                            break 'b;
-                        }
-                    }
+                if control.is_blocked(about.id) {
+                    log::info!("image;blocked;id={}", about.id);
+                    continue;
                 }
+
+                let is_large = about.files.len() > config.large_gallery_pages();
+
+                if is_large && large >= max_large {
+                    log::debug!("image;deferred;id={}", about.id);
+                    deferred.push_back(about);
+                    continue;
+                }
+
+                if is_large {
+                    large += 1;
+                }
+
+                running.push(start(about, is_large));
             }
 
+            // 진행 중이던 작품과 기다리던 작품은 다음에 시작할 때 `About`에서 이어서 진행함
+            if !running.is_empty() || !deferred.is_empty() {
+                log::info!(
+                    "image;stop;in_progress={};deferred={}",
+                    running.len(),
+                    deferred.len()
+                );
+            }
+
+            drop(running);
+
             log::debug!("shutdown_image");
 
             stop_sender.send(()).unwrap();
@@ -82,14 +226,254 @@ impl ComponentLifecycle for Image {
     }
 }
 
+/// 작품 하나의 이미지들을 받아서 `Sync`로 보냄
+///
+/// 진행 중인 작품들은 `download_concurrency`를 순서대로 나눠서 씀
+#[allow(clippy::too_many_arguments)]
+async fn process(
+    config: &Config,
+    channel: &container::Channel,
+    token: &container::Token,
+    job: &container::Job,
+    control: &container::Control,
+    health: &container::Health,
+    rate_limit: &container::RateLimit,
+    downloads: &Semaphore,
+    about: crawler::model::Gallery,
+) {
+    let total_page = about.files.len();
+    let forced = control.take_forced(about.id);
+
+    // 페이지가 없는 작품은 release하지 않음 (`RetryQueue`도 다시 시도하지 않음)
+    if total_page == 0 {
+        channel
+            .err_tx()
+            .send((Some(about.id), None, None, Error::NoPages.into()));
+
+        let _r = job.failed(about.id).to(about.id, channel.err_tx()).await;
+
+        return;
+    }
+
+    // 이전에 올리다가 멈춘 작품이라면 올리지 못 한 페이지부터 진행함
+    let uploaded = match uploaded(config, token, job, rate_limit, &about, forced)
+        .to(about.id, channel.err_tx())
+        .await
+    {
+        Some(uploaded) => uploaded,
+        None => return,
+    };
+
+    channel
+        .progress_tx()
+        .send(ProgressKind::Begin(
+            about.id,
+            uploaded.pages.clone(),
+            total_page,
+            uploaded.thumbnail,
+        ))
+        .await
+        .unwrap();
+
+    let id = about.id;
+
+    // 썸네일은 0 페이지로 첫 번째 파일에서 받음
+    let thumbnail = !uploaded.thumbnail && total_page > 0;
+
+    let pages = thumbnail
+        .then_some(0)
+        .into_iter()
+        .chain((1..=total_page).filter(|page| !uploaded.pages.contains(page)))
+        .collect::<Vec<_>>();
+
+    // 작품 하나에서 동시에 받는 페이지 수는 `download_concurrency_per_gallery`
+    // 모든 작품을 합쳐서 동시에 받는 페이지 수는 `download_concurrency`
+    let cancelled = stream::iter(pages)
+        .map(|page| {
+            let file = &about.files[page.max(1) - 1];
+
+            async move {
+                // 진행 중인 작품도 마도메 서버가 응답하지 않는다면 응답할 때까지 기다림
+                health.wait_healthy().await;
+
+                if control.is_blocked(id) {
+                    return true;
+                }
+
+                let _permit = downloads.acquire().await.unwrap();
+                let started = Instant::now();
+                let dir = config.cache_dir();
+
+                // 실패한 페이지는 건너뛰고 나머지 페이지를 계속 진행함
+                // 못 올린 페이지는 다음에 다시 시도할 때 이어서 올림
+                let downloaded = retry(config.retry_policy(), "download_image", || {
+                    let kind = match page {
+                        0 => crawler::image::ImageKind::Thumbnail,
+                        _ => crawler::image::ImageKind::Original,
+                    };
+
+                    download_image(rate_limit, &dir, id, page, file, kind)
+                })
+                .too(id, page, total_page, channel.err_tx())
+                .await;
+
+                if let Some((image, staged, cached)) = downloaded {
+                    if cached {
+                        log::debug!("image;cached;id={id};page={page}/{total_page}");
+                    } else {
+                        channel.send_event(container::PipelineEvent::PageDownloaded {
+                            id,
+                            page,
+                            total_page,
+                            bytes: staged.len(),
+                            elapsed_ms: started.elapsed().as_millis() as u64,
+                        });
+                    }
+
+                    channel
+                        .send_image(id, page, total_page, image, staged)
+                        .await;
+                }
+
+                false
+            }
+        })
+        .buffer_unordered(config.download_concurrency_per_gallery())
+        .fold(false, |cancelled, x| async move { cancelled || x })
+        .await;
+
+    if cancelled {
+        log::info!("image;cancelled;id={id}");
+
+        let _r = job.failed(id).to(id, channel.err_tx()).await;
+        control.notify_cancelled(id);
+    }
+}
+
+/// 받아서 `dir`에 저장함
+///
+/// 이전에 받아두고 올리지 못 한 이미지가 있다면 다시 받지 않고 `true`를 같이 반환함
 async fn download_image(
+    rate_limit: &container::RateLimit,
+    dir: &str,
     id: u32,
+    page: usize,
     file: &crawler::model::File,
     kind: crawler::image::ImageKind,
-) -> Result<(crawler::image::Image, Bytes), Error> {
+) -> Result<(crawler::image::Image, Staged, bool), Error> {
     let image = crawler::image::Image::new(id, file, kind).await?;
 
+    if let Some(staged) = cache::get(dir, id, page, &file.hash, kind).await? {
+        return Ok((image, staged, true));
+    }
+
+    rate_limit.download().await;
+
     let buf = image.download().await?;
 
-    Ok((image, buf))
+    rate_limit.downloaded(buf.len());
+
+    let staged = cache::put(dir, id, page, &file.hash, kind, &buf).await?;
+
+    Ok((image, staged, false))
+}
+
+/// 이미 올라간 페이지들과 썸네일
+///
+/// 작품의 파일 목록이 이전에 저장한 것과 다르다면 바뀌지 않은 페이지들만 있음
+///
+/// `forced`라면 파일 서버에 있는 이미지도 다시 올림
+async fn uploaded(
+    config: &Config,
+    token: &container::Token,
+    job: &container::Job,
+    rate_limit: &container::RateLimit,
+    about: &crawler::model::Gallery,
+    forced: bool,
+) -> Result<Uploaded, Error> {
+    let id = about.id;
+    let total_page = about.files.len();
+
+    let files = about.files.iter().map(|x| x.hash.clone()).collect();
+
+    let changed = job.snapshot(id, files).await?;
+
+    let (mut pages, mut thumbnail) = job
+        .get(id)
+        .await
+        .map(|x| (x.pages, x.thumbnail))
+        .unwrap_or_default();
+
+    // 바뀐 페이지도 파일 서버에는 이전 이미지가 있음
+    if changed {
+        log::info!("image_resume;id={id};files_changed");
+    } else if config.image_resume() && !forced {
+        let (uploaded_pages, uploaded_thumbnail) =
+            get_uploaded(config, token, rate_limit, id).await?;
+
+        for page in uploaded_pages {
+            if page > total_page || pages.contains(&page) {
+                continue;
+            }
+
+            // 업로드는 됐는데 기록하기 전에 멈췄던 페이지
+            job.page_uploaded(id, page, total_page).await?;
+            pages.insert(page);
+        }
+
+        if uploaded_thumbnail && !thumbnail {
+            job.thumbnail_uploaded(id).await?;
+            thumbnail = true;
+        }
+    }
+
+    pages.retain(|page| (1..=total_page).contains(page));
+
+    if !pages.is_empty() {
+        log::info!(
+            "image_resume;id={id};uploaded={}/{total_page};thumbnail={thumbnail}",
+            pages.len()
+        );
+    }
+
+    Ok(Uploaded { pages, thumbnail })
+}
+
+#[derive(Default)]
+struct Uploaded {
+    pages: BTreeSet<usize>,
+    thumbnail: bool,
+}
+
+/// (pages, thumbnail)
+///
+/// `image/library/{id}/{page}.{ext}`, `image/library/{id}/thumbnail.{ext}`
+#[allow(clippy::await_holding_lock)]
+pub(super) async fn get_uploaded(
+    config: &Config,
+    token: &container::Token,
+    rate_limit: &container::RateLimit,
+    id: u32,
+) -> Result<(Vec<usize>, bool), file::Error> {
+    rate_limit.library().await;
+
+    let (_lock, token) = token.as_behavior();
+
+    let xs = file::list(&config.file_url(), token, format!("image/library/{id}/")).await?;
+
+    let names = xs
+        .iter()
+        .filter(|x| x.size > 0)
+        .filter_map(|x| {
+            let name = x.path.rsplit('/').next()?;
+            let (name, _ext) = name.split_once('.')?;
+
+            Some(name)
+        })
+        .collect::<Vec<_>>();
+
+    let pages = names.iter().filter_map(|x| x.parse().ok()).collect();
+    let thumbnail = names.contains(&"thumbnail");
+
+    Ok((pages, thumbnail))
 }
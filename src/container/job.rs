@@ -0,0 +1,441 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    path::PathBuf,
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use sai::{Component, ComponentLifecycle};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, sync::Mutex};
+
+use crate::storage;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Io: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// # Job
+///
+/// 작품 별로 어디까지 진행됐는지를 디스크에 저장합니다.
+///
+/// - `job/queue/{id}.json`: 작품 번호만 가져온 상태
+/// - `job/progress/{id}.json`: 작품 정보를 올렸고, 이미지를 올리는 중인 상태
+/// - `job/done/{id}.json`: release까지 끝난 상태
+///
+/// 앱이 중간에 꺼져도 재시작할 때 각 컨테이너가 이걸 보고 남은 작업만 이어서 진행합니다.
+#[derive(Component)]
+#[lifecycle]
+pub struct Job {
+    store: Option<Mutex<HashMap<u32, JobJson>>>,
+    /// 작품마다 파일에 쓰는 순서를 지키기 위한 lock
+    ///
+    /// 파일에 쓰는 동안 `store`를 잡고 있지 않도록 따로 둠 (작품 수만큼만 늘어남)
+    writing: Option<parking_lot::Mutex<HashMap<u32, Arc<Mutex<()>>>>>,
+}
+
+#[async_trait::async_trait]
+impl ComponentLifecycle for Job {
+    async fn start(&mut self) {
+        let mut store = HashMap::new();
+
+        for stage in [Stage::Discovered, Stage::AboutSynced, Stage::Released] {
+            let jobs = match read_dir(stage.dir()).await {
+                Ok(jobs) => jobs,
+                Err(err) => {
+                    log::error!("job;{};{err}", stage.dir());
+                    eprintln!("can not read {}: {err}", stage.dir());
+                    std::process::exit(1);
+                }
+            };
+
+            for job in jobs {
+                store.insert(job.id, job);
+            }
+        }
+
+        log::info!("job;loaded={}", store.len());
+
+        self.store.replace(Mutex::new(store));
+        self.writing.replace(Default::default());
+    }
+}
+
+impl Job {
+    pub async fn get(&self, id: u32) -> Option<JobJson> {
+        let store = self.store.as_ref().unwrap().lock().await;
+
+        store.get(&id).cloned()
+    }
+
+    /// release되지 않은 작업들
+    pub async fn unfinished(&self) -> Vec<JobJson> {
+        let store = self.store.as_ref().unwrap().lock().await;
+
+        let mut xs = store
+            .values()
+            .filter(|x| x.stage != Stage::Released)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        xs.sort_by_key(|x| x.id);
+
+        xs
+    }
+
+    /// 실패하지 않았고 release되지 않은 작업이 있는지
+    ///
+    /// `blocked`인 작품(`Control`로 cancel, skip한 작품)과 `timeout`동안 바뀌지 않은 작업은 멈춘 것으로 봄
+    pub async fn in_progress(
+        &self,
+        blocked: impl Fn(u32) -> bool,
+        timeout: chrono::Duration,
+    ) -> bool {
+        let store = self.store.as_ref().unwrap().lock().await;
+
+        let before = Utc::now() - timeout;
+
+        store.values().any(|x| {
+            x.stage != Stage::Released && !x.failed && !blocked(x.id) && x.updated_at > before
+        })
+    }
+
+    /// 마지막으로 확인한 지 `before`보다 오래된 release된 작업들, 오래된 순서
+    pub async fn stale(&self, before: DateTime<Utc>, limit: usize) -> Vec<JobJson> {
+        let store = self.store.as_ref().unwrap().lock().await;
+
+        let mut xs = store
+            .values()
+            .filter(|x| x.stage == Stage::Released && x.checked_at() < before)
+            .cloned()
+            .collect::<Vec<_>>();
+
+        xs.sort_by_key(|x| x.checked_at());
+        xs.truncate(limit);
+
+        xs
+    }
+
+    /// 이미 있는 작업이라면 아무것도 하지 않음
+    pub async fn discovered(&self, id: u32) -> Result<(), Error> {
+        let writing = self.writing(id);
+        let _writing = writing.lock().await;
+
+        if self.store.as_ref().unwrap().lock().await.contains_key(&id) {
+            return Ok(());
+        }
+
+        let job = JobJson::new(id);
+
+        job.write(None).await?;
+
+        self.store.as_ref().unwrap().lock().await.insert(id, job);
+
+        Ok(())
+    }
+
+    /// `hash`: 올린 작품 정보의 해시 (`Updater`)
+    pub async fn about_synced(
+        &self,
+        id: u32,
+        total_page: usize,
+        hash: String,
+    ) -> Result<(), Error> {
+        self.update(id, |job| {
+            job.hash = Some(hash);
+            job.checked_at = Some(Utc::now());
+            // 이미 올라간 이미지가 있다면 이어서 올림
+            job.stage = if job.pages.is_empty() {
+                Stage::AboutSynced
+            } else {
+                Stage::Image
+            };
+            job.total_page = total_page;
+        })
+        .await
+    }
+
+    pub async fn page_uploaded(
+        &self,
+        id: u32,
+        page: usize,
+        total_page: usize,
+    ) -> Result<(), Error> {
+        self.update(id, |job| {
+            job.stage = Stage::Image;
+            job.total_page = total_page;
+            job.pages.insert(page);
+        })
+        .await
+    }
+
+    pub async fn thumbnail_uploaded(&self, id: u32) -> Result<(), Error> {
+        self.update(id, |job| {
+            job.thumbnail = true;
+        })
+        .await
+    }
+
+    /// `Updater`가 작품 정보가 바뀌지 않은 걸 확인함
+    pub async fn checked(&self, id: u32) -> Result<(), Error> {
+        self.update(id, |job| {
+            job.checked_at = Some(Utc::now());
+        })
+        .await
+    }
+
+    /// 해시를 저장하기 전에 release된 작품은 `Updater`가 처음 확인한 작품 정보의 해시를 저장함
+    pub async fn hashed(&self, id: u32, hash: String) -> Result<(), Error> {
+        self.update(id, |job| {
+            job.hash = Some(hash);
+            job.checked_at = Some(Utc::now());
+        })
+        .await
+    }
+
+    /// 파일 서버에 없는 페이지와 썸네일을 지워서 다음에 다시 올리도록 함
+    pub async fn not_uploaded(
+        &self,
+        id: u32,
+        pages: &[usize],
+        thumbnail: bool,
+    ) -> Result<(), Error> {
+        self.update(id, |job| {
+            for page in pages {
+                job.pages.remove(page);
+            }
+
+            if thumbnail {
+                job.thumbnail = false;
+            }
+        })
+        .await
+    }
+
+    /// 작품의 파일 목록을 저장함
+    ///
+    /// 이전에 저장한 파일 목록과 다르다면 바뀐 페이지를 지우고 `true`를 반환함
+    pub async fn snapshot(&self, id: u32, files: Vec<String>) -> Result<bool, Error> {
+        let mut changed = false;
+
+        self.update(id, |job| {
+            if job.files == files {
+                return;
+            }
+
+            if !job.files.is_empty() {
+                changed = true;
+
+                // 같은 페이지에 같은 파일이 있다면 다시 올리지 않음
+                let unchanged = |page: usize| job.files.get(page - 1) == files.get(page - 1);
+
+                job.pages.retain(|page| unchanged(*page));
+                job.thumbnail = job.thumbnail && unchanged(1);
+
+                if job.stage == Stage::Image {
+                    job.stage = Stage::AboutSynced;
+                }
+            }
+
+            job.files = files;
+        })
+        .await?;
+
+        Ok(changed)
+    }
+
+    /// 처음부터 다시 진행함
+    pub async fn reset(&self, id: u32) -> Result<(), Error> {
+        self.update(id, |job| {
+            *job = JobJson::new(id);
+        })
+        .await
+    }
+
+    /// 실패한 작업을 다시 시도함
+    pub async fn requeued(&self, id: u32) -> Result<(), Error> {
+        self.update(id, |job| {
+            job.failed = false;
+            job.retries += 1;
+        })
+        .await
+    }
+
+    pub async fn released(&self, id: u32) -> Result<(), Error> {
+        self.update(id, |job| {
+            job.stage = Stage::Released;
+            job.failed = false;
+        })
+        .await
+    }
+
+    pub async fn failed(&self, id: u32) -> Result<(), Error> {
+        self.update(id, |job| {
+            job.failed = true;
+        })
+        .await
+    }
+
+    async fn update(&self, id: u32, f: impl FnOnce(&mut JobJson) + Send) -> Result<(), Error> {
+        let writing = self.writing(id);
+        let _writing = writing.lock().await;
+
+        let (job, prev_stage) = {
+            let mut store = self.store.as_ref().unwrap().lock().await;
+
+            let job = store.entry(id).or_insert_with(|| JobJson::new(id));
+            let prev_stage = job.stage;
+
+            f(job);
+            job.updated_at = Utc::now();
+
+            (job.clone(), prev_stage)
+        };
+
+        job.write(Some(prev_stage)).await?;
+
+        Ok(())
+    }
+
+    fn writing(&self, id: u32) -> Arc<Mutex<()>> {
+        self.writing
+            .as_ref()
+            .unwrap()
+            .lock()
+            .entry(id)
+            .or_default()
+            .clone()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stage {
+    Discovered,
+    AboutSynced,
+    /// 이미지를 한 장 이상 올림
+    Image,
+    Released,
+}
+
+impl Stage {
+    fn dir(&self) -> &'static str {
+        match self {
+            Self::Discovered => "job/queue",
+            Self::AboutSynced | Self::Image => "job/progress",
+            Self::Released => "job/done",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobJson {
+    pub id: u32,
+    pub stage: Stage,
+    pub total_page: usize,
+    /// 업로드에 성공한 페이지
+    pub pages: BTreeSet<usize>,
+    /// 썸네일 업로드에 성공했는지
+    #[serde(default)]
+    pub thumbnail: bool,
+    /// 작품의 파일 목록 (file hash)
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// 마지막 시도가 실패했는지
+    pub failed: bool,
+    /// `RetryQueue`에서 다시 시도한 횟수
+    #[serde(default)]
+    pub retries: usize,
+    /// 작품 정보의 해시 (제목, 태그, 파일 목록)
+    #[serde(default)]
+    pub hash: Option<String>,
+    /// `Updater`가 마지막으로 작품 정보를 확인한 시간
+    #[serde(default)]
+    pub checked_at: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl JobJson {
+    /// 모든 페이지와 썸네일을 올렸는지
+    ///
+    /// 페이지가 없는 작품은 release하지 않음
+    pub fn is_uploaded(&self) -> bool {
+        self.total_page > 0 && self.pages.len() >= self.total_page && self.thumbnail
+    }
+
+    /// 확인한 적이 없다면 마지막으로 바뀐 시간
+    fn checked_at(&self) -> DateTime<Utc> {
+        self.checked_at.unwrap_or(self.updated_at)
+    }
+
+    fn new(id: u32) -> Self {
+        Self {
+            id,
+            stage: Stage::Discovered,
+            total_page: 0,
+            pages: BTreeSet::new(),
+            thumbnail: false,
+            files: Vec::new(),
+            failed: false,
+            retries: 0,
+            hash: None,
+            checked_at: None,
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn path(&self, stage: Stage) -> PathBuf {
+        PathBuf::from(stage.dir()).join(format!("{}.json", self.id))
+    }
+
+    /// 단계가 바뀌었다면 이전 폴더에 있던 파일은 지움
+    async fn write(&self, prev_stage: Option<Stage>) -> io::Result<()> {
+        fs::create_dir_all(self.stage.dir()).await?;
+
+        storage::write_json(self.path(self.stage), self).await?;
+
+        match prev_stage {
+            Some(prev) if prev.dir() != self.stage.dir() => {
+                match fs::remove_file(self.path(prev)).await {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                    _ => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+async fn read_dir(dir: &str) -> io::Result<Vec<JobJson>> {
+    let mut xs = Vec::new();
+
+    let mut entries = match fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(xs),
+        Err(err) => return Err(err),
+    };
+
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+
+        // 쓰다가 멈춘 임시 파일이나 옮겨둔 파일
+        if path.extension().and_then(|x| x.to_str()) != Some("json") {
+            continue;
+        }
+
+        match storage::read_json::<JobJson>(&path).await {
+            Ok(Some(job)) => xs.push(job),
+            Ok(None) => {}
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                log::error!("job;{err}");
+
+                storage::quarantine(&path).await?;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(xs)
+}
@@ -0,0 +1,72 @@
+use sai::{Component, ComponentLifecycle, Injected};
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+use crate::container::{self, PipelineEvent};
+
+/// # Logger
+///
+/// `PipelineEvent`를 구독해서 로그로 남깁니다.
+///
+/// 에러는 `ErrorManager`가 남기기 때문에 `Failed`는 남기지 않음
+#[derive(Component)]
+#[lifecycle]
+pub struct Logger {
+    #[injected]
+    channel: Injected<container::Channel>,
+
+    tx: Option<mpsc::Sender<()>>,
+    rx: Option<oneshot::Receiver<()>>,
+}
+
+#[async_trait::async_trait]
+impl ComponentLifecycle for Logger {
+    async fn start(&mut self) {
+        let (stop_sender, rx) = oneshot::channel();
+        let (tx, mut stop_receiver) = mpsc::channel(1);
+
+        self.tx.replace(tx);
+        self.rx.replace(rx);
+
+        let mut events = self.channel.subscribe_event();
+
+        tokio::spawn(async move {
+            loop {
+                let event = tokio::select! {
+                    _ = stop_receiver.recv() => {
+                        break;
+                    }
+                    event = events.recv() => {
+                        event
+                    }
+                };
+
+                match event {
+                    Ok(PipelineEvent::Failed(_)) => {}
+                    Ok(
+                        event @ (PipelineEvent::PageDownloaded { .. }
+                        | PipelineEvent::PageUploaded { .. }),
+                    ) => {
+                        log::debug!("{event}");
+                    }
+                    Ok(event) => {
+                        log::info!("{event}");
+                    }
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        log::warn!("logger;lagged={count}");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+
+            log::debug!("shutdown_logger");
+
+            stop_sender.send(()).unwrap();
+        });
+    }
+
+    async fn stop(&mut self) {
+        self.tx.take().unwrap().send(()).await.unwrap();
+
+        self.rx.take().unwrap().await.unwrap();
+    }
+}
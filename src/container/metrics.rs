@@ -0,0 +1,404 @@
+use std::{collections::BTreeMap, fmt::Write, io, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+use sai::{Component, ComponentLifecycle, Injected};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, mpsc, oneshot},
+    time::timeout,
+};
+
+use crate::{
+    config::Config,
+    container::{self, PipelineEvent},
+};
+
+const COUNTERS: [(&str, &str); 10] = [
+    (
+        "sync_galleries_discovered_total",
+        "Galleries not in madome found by Nozomi and Backfill",
+    ),
+    (
+        "sync_galleries_synced_total",
+        "Galleries whose metadata was uploaded to the library server",
+    ),
+    ("sync_galleries_released_total", "Released galleries"),
+    (
+        "sync_errors_total",
+        "Errors recorded by ErrorManager, a gallery can record several",
+    ),
+    ("sync_pages_downloaded_total", "Downloaded pages"),
+    ("sync_pages_uploaded_total", "Uploaded pages"),
+    ("sync_downloaded_bytes_total", "Downloaded bytes"),
+    ("sync_uploaded_bytes_total", "Uploaded bytes"),
+    ("sync_token_refresh_total", "Token refresh attempts"),
+    (
+        "sync_metrics_lagged_events_total",
+        "Events dropped before Metrics received them, counters above undercount by these",
+    ),
+];
+
+const HISTOGRAMS: [(&str, &str, &[f64]); 2] = [
+    (
+        "sync_cycle_discovered",
+        "Galleries discovered per Nozomi cycle",
+        &[0.0, 1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0],
+    ),
+    (
+        "sync_stage_duration_seconds",
+        "Duration of each pipeline stage including retries",
+        &[
+            0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+        ],
+    ),
+];
+
+/// `Channel::queue_stats`의 depth, capacity, high_water
+const GAUGES: [(&str, &str); 3] = [
+    (
+        "sync_queue_depth",
+        "Messages waiting in each channel, bytes for sync_bytes",
+    ),
+    (
+        "sync_queue_capacity",
+        "Capacity of each channel, bytes for sync_bytes",
+    ),
+    (
+        "sync_queue_high_water",
+        "Highest depth of each channel since start",
+    ),
+];
+
+/// # Metrics
+///
+/// `PipelineEvent`를 구독해서 모은 값들을 prometheus가 가져갈 수 있도록 `/metrics`로 보여줍니다.
+///
+/// - counter: 찾은 작품, 올린 작품, release한 작품, 에러, 받은/올린 페이지와 bytes, 토큰 갱신
+/// - histogram: 사이클마다 찾은 작품 수, 단계마다 걸린 시간
+/// - gauge: `Channel`의 채널마다 쌓여있는 수, 용량, 가장 많이 쌓였던 수
+///
+/// `metrics_addr`가 설정되어 있을 때만 열음
+///
+/// 재시작하면 초기화됨
+#[derive(Component)]
+#[lifecycle]
+pub struct Metrics {
+    #[injected]
+    config: Injected<Config>,
+
+    #[injected]
+    channel: Injected<container::Channel>,
+
+    tx: Option<mpsc::Sender<()>>,
+    rx: Option<oneshot::Receiver<()>>,
+}
+
+#[async_trait::async_trait]
+impl ComponentLifecycle for Metrics {
+    async fn start(&mut self) {
+        let listener = match self.config.metrics_addr() {
+            Some(addr) => match TcpListener::bind(addr).await {
+                Ok(listener) => {
+                    log::info!("metrics;listen={addr}");
+                    Some(listener)
+                }
+                Err(err) => {
+                    log::error!("Metrics: bind {addr}: {err}");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        // 열지 않았다면 아무것도 하지 않음
+        let listener = match listener {
+            Some(listener) => listener,
+            None => return,
+        };
+
+        let (stop_sender, rx) = oneshot::channel();
+        let (tx, mut stop_receiver) = mpsc::channel(1);
+
+        self.tx.replace(tx);
+        self.rx.replace(rx);
+
+        let channel = self.channel.clone();
+        let mut events = self.channel.subscribe_event();
+        let store = Arc::new(Mutex::new(Store::default()));
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = stop_receiver.recv() => {
+                        break;
+                    }
+                    event = events.recv() => match event {
+                        Ok(event) => store.lock().record(&event),
+                        Err(broadcast::error::RecvError::Lagged(count)) => {
+                            log::warn!("metrics;lagged={count}");
+
+                            store.lock().inc("sync_metrics_lagged_events_total", "", count);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    accepted = listener.accept() => {
+                        let (stream, peer) = match accepted {
+                            Ok(accepted) => accepted,
+                            Err(err) => {
+                                log::error!("Metrics: {err}");
+                                continue;
+                            }
+                        };
+
+                        let body = store.lock().render(&channel);
+
+                        tokio::spawn(async move {
+                            if let Err(err) = serve(stream, body).await {
+                                log::debug!("metrics;peer={peer};{err}");
+                            }
+                        });
+                    }
+                }
+            }
+
+            log::debug!("shutdown_metrics");
+
+            stop_sender.send(()).unwrap();
+        });
+    }
+
+    async fn stop(&mut self) {
+        // 열지 않았음
+        if self.rx.is_none() {
+            return;
+        }
+
+        self.tx.take().unwrap().send(()).await.unwrap();
+
+        self.rx.take().unwrap().await.unwrap();
+    }
+}
+
+/// `GET /metrics`만 받음
+async fn serve(mut stream: TcpStream, body: String) -> io::Result<()> {
+    let mut buf = Vec::new();
+    let mut chunk = [0; 1024];
+
+    // 헤더까지만 읽음
+    while !buf.windows(4).any(|x| x == b"\r\n\r\n") && buf.len() < 8192 {
+        let n = match timeout(Duration::from_secs(5), stream.read(&mut chunk)).await {
+            Ok(n) => n?,
+            Err(_elapsed) => return Err(io::ErrorKind::TimedOut.into()),
+        };
+
+        if n == 0 {
+            break;
+        }
+
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    let request = String::from_utf8_lossy(&buf);
+    let mut line = request.lines().next().unwrap_or_default().split(' ');
+
+    let (status, body) = match (line.next(), line.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", body),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[derive(Default)]
+struct Store {
+    /// (name, labels)
+    counters: BTreeMap<(&'static str, String), u64>,
+    histograms: BTreeMap<(&'static str, String), Histogram>,
+}
+
+impl Store {
+    fn record(&mut self, event: &PipelineEvent) {
+        match event {
+            PipelineEvent::CycleFinished {
+                discovered,
+                elapsed_ms,
+                ..
+            } => {
+                self.observe("sync_cycle_discovered", "", *discovered as f64);
+                self.observe_stage("nozomi_cycle", *elapsed_ms);
+            }
+            PipelineEvent::GalleryDiscovered { .. } => {
+                self.inc("sync_galleries_discovered_total", "", 1);
+            }
+            PipelineEvent::AboutParsed { elapsed_ms, .. } => {
+                self.observe_stage("parse_about", *elapsed_ms);
+            }
+            PipelineEvent::AboutSynced { elapsed_ms, .. } => {
+                self.inc("sync_galleries_synced_total", "", 1);
+                self.observe_stage("sync_about", *elapsed_ms);
+            }
+            PipelineEvent::PageDownloaded {
+                bytes, elapsed_ms, ..
+            } => {
+                self.inc("sync_pages_downloaded_total", "", 1);
+                self.inc("sync_downloaded_bytes_total", "", *bytes as u64);
+                self.observe_stage("download_image", *elapsed_ms);
+            }
+            PipelineEvent::PageUploaded {
+                bytes, elapsed_ms, ..
+            } => {
+                self.inc("sync_pages_uploaded_total", "", 1);
+                self.inc("sync_uploaded_bytes_total", "", *bytes as u64);
+                self.observe_stage("sync_image", *elapsed_ms);
+            }
+            PipelineEvent::Released { elapsed_ms, .. } => {
+                self.inc("sync_galleries_released_total", "", 1);
+                self.observe_stage("release_book", *elapsed_ms);
+            }
+            PipelineEvent::Failed(x) => {
+                let kind = format!("{:?}", x.kind).to_lowercase();
+
+                self.inc("sync_errors_total", &format!("kind=\"{kind}\""), 1);
+            }
+            PipelineEvent::TokenRefreshed { ok } => {
+                let result = if *ok { "ok" } else { "error" };
+
+                self.inc(
+                    "sync_token_refresh_total",
+                    &format!("result=\"{result}\""),
+                    1,
+                );
+            }
+            PipelineEvent::CycleStarted
+            | PipelineEvent::Progress { .. }
+            | PipelineEvent::Cancelled { .. }
+            | PipelineEvent::Token { .. }
+            | PipelineEvent::Health { .. } => {}
+        }
+    }
+
+    fn inc(&mut self, name: &'static str, labels: &str, x: u64) {
+        *self.counters.entry((name, labels.to_string())).or_default() += x;
+    }
+
+    fn observe(&mut self, name: &'static str, labels: &str, x: f64) {
+        let buckets = HISTOGRAMS
+            .iter()
+            .find(|(n, ..)| *n == name)
+            .map(|(.., buckets)| *buckets)
+            .unwrap();
+
+        self.histograms
+            .entry((name, labels.to_string()))
+            .or_insert_with(|| Histogram::new(buckets))
+            .observe(x);
+    }
+
+    fn observe_stage(&mut self, stage: &str, elapsed_ms: u64) {
+        self.observe(
+            "sync_stage_duration_seconds",
+            &format!("stage=\"{stage}\""),
+            elapsed_ms as f64 / 1000.0,
+        );
+    }
+
+    /// prometheus text format
+    fn render(&self, channel: &container::Channel) -> String {
+        let mut out = String::new();
+
+        for (name, help) in COUNTERS {
+            let _r = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+
+            for ((_, labels), x) in self.counters.iter().filter(|((n, _), _)| *n == name) {
+                let _r = writeln!(out, "{name}{} {x}", braces(labels));
+            }
+        }
+
+        for (name, help, _) in HISTOGRAMS {
+            let _r = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+
+            for ((_, labels), x) in self.histograms.iter().filter(|((n, _), _)| *n == name) {
+                x.render(&mut out, name, labels);
+            }
+        }
+
+        let queues = channel.queue_stats();
+
+        for (i, (name, help)) in GAUGES.iter().enumerate() {
+            let _r = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge");
+
+            for x in &queues {
+                let value = [x.depth, x.capacity, x.high_water][i];
+
+                let _r = writeln!(out, "{name}{{queue=\"{}\"}} {value}", x.name);
+            }
+        }
+
+        out
+    }
+}
+
+struct Histogram {
+    buckets: &'static [f64],
+    /// 각 bucket에 해당하는 수, 누적하지 않음
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            counts: vec![0; buckets.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, x: f64) {
+        if let Some(i) = self.buckets.iter().position(|le| x <= *le) {
+            self.counts[i] += 1;
+        }
+
+        self.sum += x;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+
+        for (le, x) in self.buckets.iter().zip(&self.counts) {
+            cumulative += x;
+
+            let _r = writeln!(
+                out,
+                "{name}_bucket{{{labels}{sep}le=\"{le}\"}} {cumulative}"
+            );
+        }
+
+        let _r = writeln!(
+            out,
+            "{name}_bucket{{{labels}{sep}le=\"+Inf\"}} {}",
+            self.count
+        );
+        let _r = writeln!(out, "{name}_sum{} {}", braces(labels), self.sum);
+        let _r = writeln!(out, "{name}_count{} {}", braces(labels), self.count);
+    }
+}
+
+fn braces(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{labels}}}")
+    }
+}
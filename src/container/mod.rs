@@ -1,21 +1,41 @@
 pub mod about;
+mod backfill;
 mod channel;
+mod control;
 mod error;
+mod event;
+pub mod health;
 pub mod image;
+pub mod job;
+mod logger;
+mod metrics;
 pub mod nozomi;
 mod progress;
+mod rate_limit;
+mod retry_queue;
 pub mod sync;
 pub mod token;
+mod updater;
 mod websocket;
 
 pub use about::About;
+pub use backfill::Backfill;
 pub use channel::*;
+pub use control::Control;
 pub use error::ErrorManager;
+pub use event::PipelineEvent;
+pub use health::Health;
 pub use image::Image;
+pub use job::Job;
+pub use logger::Logger;
+pub use metrics::Metrics;
 pub use nozomi::Nozomi;
 pub use progress::*;
+pub use rate_limit::RateLimit;
+pub use retry_queue::RetryQueue;
 pub use sync::{Sync, SyncKind};
 pub use token::{Token, TokenJson, TokenRwLock};
+pub use updater::Updater;
 pub use websocket::WebSocket;
 
 // nozomi -> about -> sync -> image -> sync
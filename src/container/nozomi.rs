@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::time::Instant;
 
 use madome_sdk::api::library;
 use sai::{Component, ComponentLifecycle, Injected};
@@ -7,7 +7,11 @@ use tokio::{
     time::sleep,
 };
 
-use crate::{config::Config, container, SendError};
+use crate::{
+    config::Config,
+    container::{self, job::Stage, PipelineEvent},
+    SendError,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -37,6 +41,18 @@ pub struct Nozomi {
     #[injected]
     token: Injected<container::Token>,
 
+    #[injected]
+    job: Injected<container::Job>,
+
+    #[injected]
+    control: Injected<container::Control>,
+
+    #[injected]
+    health: Injected<container::Health>,
+
+    #[injected]
+    rate_limit: Injected<container::RateLimit>,
+
     tx: Option<mpsc::Sender<()>>,
     rx: Option<oneshot::Receiver<()>>,
 }
@@ -53,59 +69,126 @@ impl ComponentLifecycle for Nozomi {
         let config = self.config.clone();
         let channel = self.channel.clone();
         let token = self.token.clone();
+        let job = self.job.clone();
+        let control = self.control.clone();
+        let health = self.health.clone();
+        let rate_limit = self.rate_limit.clone();
 
         tokio::spawn(async move {
+            // 작품 번호만 가져오고 끝나지 않은 작업들을 먼저 보냄
+            for x in job.unfinished().await {
+                if x.stage == Stage::Discovered {
+                    log::info!("nozomi_resume;id={}", x.id);
+                    channel.id_tx().send(x.id).await.expect("closed id channel");
+                }
+            }
+
             let mut store = Vec::<u32>::new();
             let mut state = State::new(config.per_page());
 
             let mut empty_count = 0;
+            let mut started = None;
 
             loop {
-                log::debug!(
-                    "nozomi_parse;page={};per_page={}",
-                    state.page(),
-                    state.per_page()
-                );
-                let mut ids = get_ids_from_not_contains(token.as_ref(), &mut state)
-                    .to(None, channel.err_tx())
-                    .await
-                    .unwrap_or_default();
-
-                if ids.is_empty() {
-                    log::debug!("nozomi_parse;empty");
-                    empty_count += 1;
+                // 일시정지 중이거나 토큰을 사용할 수 없거나 마도메 서버가 응답하지 않는다면 기다림
+                tokio::select! {
+                    _ = stop_receiver.recv() => {
+                        break;
+                    }
+                    _ = async {
+                        control.wait_resumed().await;
+                        token.wait_usable().await;
+                        health.wait_healthy().await;
+                    } => {}
+                };
+
+                let cycle = *started.get_or_insert_with(|| {
+                    // 사이클 사이에 받은 abort는 다음 사이클을 멈추지 않도록 무시함
+                    if control.take_aborted() {
+                        log::info!("nozomi_parse;abort;ignored");
+                    }
+
+                    channel.send_event(PipelineEvent::CycleStarted);
+                    Instant::now()
+                });
+
+                let aborted = control.take_aborted();
+                let mut discovered = 0;
+
+                if aborted {
+                    log::info!("nozomi_parse;abort");
                 } else {
-                    log::debug!("nozomi_parse;not_empty");
-                    empty_count = 0;
-                    store.append(&mut ids);
-                }
+                    log::debug!(
+                        "nozomi_parse;page={};per_page={}",
+                        state.page(),
+                        state.per_page()
+                    );
+                    let mut ids =
+                        get_ids_from_not_contains(&config, &token, &rate_limit, &mut state)
+                            .to(None, channel.err_tx())
+                            .await
+                            .unwrap_or_default();
+
+                    if ids.is_empty() {
+                        log::debug!("nozomi_parse;empty");
+                        empty_count += 1;
+                    } else {
+                        log::debug!("nozomi_parse;not_empty");
+                        empty_count = 0;
+                        store.append(&mut ids);
+                    }
+
+                    if empty_count < config.empty_page_threshold() {
+                        continue;
+                    }
 
-                if empty_count >= 3 {
                     log::debug!("nozomi_parse;send_ids");
                     // asc
                     store.sort();
 
                     for id in store.drain(..) {
+                        // 이미 작업 중인 작품
+                        if control.is_blocked(id) || job.get(id).await.is_some() {
+                            continue;
+                        }
+
+                        if job.discovered(id).to(id, channel.err_tx()).await.is_none() {
+                            continue;
+                        }
+
+                        channel.send_event(PipelineEvent::GalleryDiscovered { id });
+                        discovered += 1;
+
                         channel.id_tx().send(id).await.expect("closed id channel");
                     }
+                }
 
-                    log::debug!("nozomi_parse;clear_state");
+                started = None;
+                channel.send_event(PipelineEvent::CycleFinished {
+                    discovered,
+                    aborted,
+                    elapsed_ms: cycle.elapsed().as_millis() as u64,
+                });
 
-                    store = Vec::new();
-                    empty_count = 0;
-                    state.clear();
+                log::debug!("nozomi_parse;clear_state");
 
-                    log::info!("nozomi_parse;sleep(180s)");
+                store = Vec::new();
+                empty_count = 0;
+                // 다시 읽은 설정을 다음 사이클부터 적용함
+                state = State::new(config.per_page());
 
-                    tokio::select! {
-                        _ = stop_receiver.recv() => {
-                            break;
-                        }
-                        _ = sleep(Duration::from_secs(180)) => {
-                            continue;
-                        }
-                    };
-                }
+                let poll_interval = config.poll_interval();
+
+                log::info!("nozomi_parse;sleep({poll_interval:?})");
+
+                tokio::select! {
+                    _ = stop_receiver.recv() => {
+                        break;
+                    }
+                    _ = sleep(poll_interval) => {
+                        continue;
+                    }
+                };
             }
 
             log::debug!("shutdown_nozomi");
@@ -143,25 +226,53 @@ impl State {
     pub fn page(&self) -> usize {
         self.page
     }
-
-    pub fn clear(&mut self) {
-        self.page = 0;
-    }
 }
 
-#[allow(clippy::await_holding_lock)]
 async fn get_ids_from_not_contains(
+    config: &Config,
     token: &container::Token,
+    rate_limit: &container::RateLimit,
     state: &mut State,
 ) -> Result<Vec<u32>, Error> {
-    let (_lock, token) = token.as_behavior();
+    let ids = parse_not_contains(
+        config,
+        token,
+        rate_limit,
+        state.next_page(),
+        state.per_page(),
+    )
+    .await?;
+
+    Ok(ids.unwrap_or_default())
+}
 
-    let ids = crawler::nozomi::parse(state.next_page(), state.per_page()).await?;
+/// 마도메에 없는 작품 번호들
+///
+/// 해당 페이지에 작품이 하나도 없다면 `None`
+#[allow(clippy::await_holding_lock)]
+pub(super) async fn parse_not_contains(
+    config: &Config,
+    token: &container::Token,
+    rate_limit: &container::RateLimit,
+    page: usize,
+    per_page: usize,
+) -> Result<Option<Vec<u32>>, Error> {
+    rate_limit.source().await;
+
+    let ids = crawler::nozomi::parse(page, per_page).await?;
+
+    if ids.is_empty() {
+        return Ok(None);
+    }
+
+    rate_limit.library().await;
+
+    let (_lock, token) = token.as_behavior();
 
-    let xs = library::get_books_by_ids("https://beta.api.madome.app", token, ids.clone()).await?;
+    let xs = library::get_books_by_ids(&config.library_url(), token, ids.clone()).await?;
     let xs = xs.iter().map(|x| x.id).collect::<Vec<_>>();
 
     let ids = ids.into_iter().filter(|id| !xs.contains(id)).collect();
 
-    Ok(ids)
+    Ok(Some(ids))
 }
@@ -1,9 +1,12 @@
-use std::{collections::HashMap, fmt::Debug, hash::Hash};
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Debug,
+};
 
 use sai::{Component, ComponentLifecycle, Injected};
 use tokio::sync::{mpsc, oneshot};
 
-use crate::container::{self, SyncKind};
+use crate::container::{self, PipelineEvent, SyncKind};
 
 #[derive(Component)]
 #[lifecycle]
@@ -27,7 +30,7 @@ impl ComponentLifecycle for Progress {
         let channel = self.channel.clone();
 
         tokio::spawn(async move {
-            let mut store = HashMap::<Key, usize>::new();
+            let mut store = HashMap::<u32, Uploading>::new();
 
             loop {
                 let received = tokio::select! {
@@ -40,27 +43,58 @@ impl ComponentLifecycle for Progress {
                 };
 
                 match received {
-                    ProgressKind::Image(id, _page, total) => {
-                        let count = *store
-                            .entry(Key::Image(id))
-                            .and_modify(|x| *x += 1)
-                            .or_insert(1);
-
-                        if count >= total {
-                            store.remove(&Key::Image(id));
-
-                            channel.sync_tx().send(SyncKind::Release(id)).await.unwrap();
-                        }
+                    ProgressKind::Begin(id, pages, total, thumbnail) => {
+                        // 이전 시도에서 남은 기록은 버림
+                        store.insert(
+                            id,
+                            Uploading {
+                                total,
+                                pages,
+                                thumbnail,
+                            },
+                        );
 
-                        let percentage = count as f32 / total as f32;
+                        // 이미지는 다 올렸는데 release 하기 전에 멈춘 작품
+                        release_if_uploaded(&channel, &mut store, id).await;
+                    }
 
-                        log::info!(
-                            "image_progress;id={id};count={count}/{total};{:.2}%",
-                            percentage * 100.0
-                        );
+                    ProgressKind::Image(id, page, total) => {
+                        let count = match store.get_mut(&id) {
+                            Some(x) if x.total == total && (1..=total).contains(&page) => {
+                                x.pages.insert(page);
+                                x.pages.len()
+                            }
+                            _ => {
+                                log::warn!("image_progress;stale;id={id};page={page}/{total}");
+                                continue;
+                            }
+                        };
+
+                        release_if_uploaded(&channel, &mut store, id).await;
+
+                        channel.send_event(PipelineEvent::Progress {
+                            id,
+                            page,
+                            count,
+                            total_page: total,
+                        });
                         // TODO: progress가 필요한 곳에 쏴주거나 아니면 서버에 전송?
                         // 필요한 곳이 서버 말고는 없는지 생각해보기
                     }
+
+                    ProgressKind::Thumbnail(id, total) => {
+                        match store.get_mut(&id) {
+                            Some(x) if x.total == total => {
+                                x.thumbnail = true;
+                            }
+                            _ => {
+                                log::warn!("thumbnail_progress;stale;id={id}");
+                                continue;
+                            }
+                        }
+
+                        release_if_uploaded(&channel, &mut store, id).await;
+                    }
                 }
             }
 
@@ -78,43 +112,56 @@ impl ComponentLifecycle for Progress {
 }
 
 pub enum ProgressKind {
+    /// Begin(id, uploaded_pages, total_page, thumbnail)
+    ///
+    /// 작품의 이미지를 올리기 시작함, 이미 올라간 페이지부터 셈
+    Begin(u32, BTreeSet<usize>, usize, bool),
     // Image(id, page, total_page, image)
     Image(u32, usize, usize),
+    /// Thumbnail(id, total_page)
+    Thumbnail(u32, usize),
 }
 
 impl Debug for ProgressKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let x = match self {
+            Self::Begin(id, pages, total, thumbnail) => {
+                format!("Begin({id}, {}, {total}, {thumbnail})", pages.len())
+            }
             Self::Image(id, page, total) => format!("Image({id}, {page}, {total})"),
+            Self::Thumbnail(id, total) => format!("Thumbnail({id}, {total})"),
         };
 
         write!(f, "ProgressKind::{x}")
     }
 }
 
-enum Key {
-    Image(u32),
+/// 올리는 중인 작품
+struct Uploading {
+    total: usize,
+    /// 업로드에 성공한 페이지
+    pages: BTreeSet<usize>,
+    thumbnail: bool,
 }
 
-impl PartialEq for Key {
-    fn eq(&self, other: &Self) -> bool {
-        match self {
-            Self::Image(x) => {
-                matches!(other, Self::Image(y) if x == y)
-            }
-        }
+impl Uploading {
+    /// 1부터 `total`까지의 모든 페이지와 썸네일을 올렸는지
+    ///
+    /// 페이지가 없는 작품은 release하지 않음 (`JobJson::is_uploaded`와 같음)
+    fn is_uploaded(&self) -> bool {
+        self.total > 0 && (1..=self.total).all(|page| self.pages.contains(&page)) && self.thumbnail
     }
 }
 
-impl Eq for Key {}
+/// 모든 페이지와 썸네일을 올렸다면 release함
+async fn release_if_uploaded(
+    channel: &container::Channel,
+    store: &mut HashMap<u32, Uploading>,
+    id: u32,
+) {
+    if matches!(store.get(&id), Some(x) if x.is_uploaded()) {
+        store.remove(&id);
 
-impl Hash for Key {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        match self {
-            Self::Image(id) => {
-                "image".hash(state);
-                id.hash(state);
-            }
-        }
+        channel.sync_tx().send(SyncKind::Release(id)).await.unwrap();
     }
 }
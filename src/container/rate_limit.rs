@@ -0,0 +1,240 @@
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use sai::{Component, ComponentLifecycle, Injected};
+use tokio::time::sleep;
+
+use crate::config::Config;
+
+/// # RateLimit
+///
+/// 원본 사이트와 마도메 서버에 보내는 요청을 token bucket으로 제한합니다.
+///
+/// - source: 원본 사이트에 보내는 요청 수 (nozomi, 작품 정보, 이미지)
+/// - download: 원본 사이트에서 받는 bytes
+/// - upload: 파일 서버에 올리는 bytes
+/// - library: library 서버에 보내는 요청 수 (파일 서버의 파일 목록도 포함)
+///
+/// 모든 컨테이너가 같은 bucket을 나눠서 씀
+///
+/// 제한은 요청할 때마다 `Config`에서 읽기 때문에 설정을 다시 읽으면 바로 적용됨 (0이라면 제한하지 않음)
+#[derive(Component)]
+#[lifecycle]
+pub struct RateLimit {
+    #[injected]
+    config: Injected<Config>,
+
+    buckets: Option<Buckets>,
+}
+
+struct Buckets {
+    source: Bucket,
+    download: Bucket,
+    upload: Bucket,
+    library: Bucket,
+}
+
+#[async_trait::async_trait]
+impl ComponentLifecycle for RateLimit {
+    async fn start(&mut self) {
+        self.buckets.replace(Buckets {
+            source: Bucket::new(),
+            download: Bucket::new(),
+            upload: Bucket::new(),
+            library: Bucket::new(),
+        });
+    }
+}
+
+impl RateLimit {
+    /// 원본 사이트에 요청을 보내기 전에 기다림
+    pub async fn source(&self) {
+        let rate = self.config.source_rps();
+
+        self.buckets().source.acquire(rate, 1).await;
+    }
+
+    /// 이미지를 받기 전에 기다림
+    ///
+    /// 받기 전에는 크기를 알 수 없기 때문에 이전에 받은 이미지들만큼 기다리고, 받은 뒤에 `downloaded`로 씀
+    pub async fn download(&self) {
+        self.source().await;
+
+        let rate = self.config.download_bytes_per_sec();
+
+        self.buckets().download.acquire(rate, 0).await;
+    }
+
+    pub fn downloaded(&self, bytes: usize) {
+        let rate = self.config.download_bytes_per_sec();
+
+        self.buckets().download.consume(rate, bytes as u64);
+    }
+
+    /// 이미지를 올리기 전에 기다림
+    pub async fn upload(&self, bytes: usize) {
+        let rate = self.config.upload_bytes_per_sec();
+
+        self.buckets().upload.acquire(rate, bytes as u64).await;
+    }
+
+    /// library 서버에 요청을 보내거나 파일 서버의 파일 목록을 가져오기 전에 기다림
+    pub async fn library(&self) {
+        let rate = self.config.library_rps();
+
+        self.buckets().library.acquire(rate, 1).await;
+    }
+
+    fn buckets(&self) -> &Buckets {
+        self.buckets.as_ref().unwrap()
+    }
+}
+
+/// 1초 동안 쓸 수 있는 만큼까지 모아둠
+///
+/// 1초에 쓸 수 있는 것보다 큰 요청(큰 이미지)은 다 모일 때까지 기다린 뒤에 쓰고, 모자란 만큼은 다음 요청들이 기다림
+struct Bucket {
+    state: Mutex<State>,
+}
+
+struct State {
+    tokens: f64,
+    last: Instant,
+}
+
+impl Bucket {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                // 처음 쓸 때 `rate`만큼으로 줄어듦
+                tokens: f64::INFINITY,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    async fn acquire(&self, rate: u64, n: u64) {
+        while let Some(wait) = self.try_acquire(rate, n) {
+            sleep(wait).await;
+        }
+    }
+
+    /// 기다려야 한다면 기다릴 시간
+    fn try_acquire(&self, rate: u64, n: u64) -> Option<Duration> {
+        if rate == 0 {
+            return None;
+        }
+
+        let mut state = self.state.lock();
+
+        state.refill(rate);
+
+        let rate = rate as f64;
+        let need = (n as f64).min(rate);
+
+        if state.tokens >= need {
+            state.tokens -= n as f64;
+            None
+        } else {
+            Some(Duration::from_secs_f64((need - state.tokens) / rate))
+        }
+    }
+
+    /// 기다리지 않고 씀
+    fn consume(&self, rate: u64, n: u64) {
+        if rate == 0 {
+            return;
+        }
+
+        let mut state = self.state.lock();
+
+        state.refill(rate);
+        state.tokens -= n as f64;
+    }
+}
+
+impl State {
+    fn refill(&mut self, rate: u64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        let rate = rate as f64;
+
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.last = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 테스트 중에 채워지는 만큼은 무시함
+    fn approx(wait: Option<Duration>, secs: f64) -> bool {
+        matches!(wait, Some(wait) if (wait.as_secs_f64() - secs).abs() < 0.05)
+    }
+
+    #[test]
+    fn unlimited() {
+        let bucket = Bucket::new();
+
+        for _ in 0..1000 {
+            assert_eq!(bucket.try_acquire(0, u64::MAX), None);
+        }
+
+        bucket.consume(0, u64::MAX);
+        assert_eq!(bucket.try_acquire(0, 1), None);
+    }
+
+    #[test]
+    fn starts_full() {
+        let bucket = Bucket::new();
+
+        // 처음에는 1초 동안 쓸 수 있는 만큼 바로 씀
+        for _ in 0..10 {
+            assert_eq!(bucket.try_acquire(10, 1), None);
+        }
+
+        assert!(approx(bucket.try_acquire(10, 1), 0.1));
+    }
+
+    #[test]
+    fn waits_for_missing_tokens() {
+        let bucket = Bucket::new();
+
+        assert_eq!(bucket.try_acquire(100, 100), None);
+        assert!(approx(bucket.try_acquire(100, 50), 0.5));
+
+        // 기다리라고 한 요청은 쓰지 않음
+        assert!(approx(bucket.try_acquire(100, 50), 0.5));
+    }
+
+    #[test]
+    fn larger_than_rate() {
+        let bucket = Bucket::new();
+
+        // 1초에 쓸 수 있는 것보다 커도 다 모였다면 씀
+        assert_eq!(bucket.try_acquire(100, 300), None);
+
+        // 모자란 200만큼과 필요한 1만큼을 기다림
+        assert!(approx(bucket.try_acquire(100, 1), 2.01));
+    }
+
+    #[test]
+    fn consume_without_waiting() {
+        let bucket = Bucket::new();
+
+        bucket.consume(100, 150);
+
+        assert!(approx(bucket.try_acquire(100, 0), 0.5));
+    }
+
+    #[test]
+    fn refill_is_capped_at_rate() {
+        let bucket = Bucket::new();
+
+        bucket.state.lock().last -= Duration::from_secs(60);
+
+        assert_eq!(bucket.try_acquire(10, 10), None);
+        assert!(approx(bucket.try_acquire(10, 1), 0.1));
+    }
+}
@@ -0,0 +1,179 @@
+use sai::{Component, ComponentLifecycle, Injected};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::sleep,
+};
+
+use crate::{
+    config::Config,
+    container::{self, about::parse_gallery, error, job::Stage, SyncKind},
+    retry::retry,
+    SendError,
+};
+
+/// # RetryQueue
+///
+/// `ErrorManager`가 남긴 `error/{id}.json`을 보고 실패한 작품을 실패한 단계부터 다시 시도합니다.
+///
+/// 진행 중인 작업이 없을 때만 시도하며, release까지 성공한 작품의 에러 파일은 지웁니다.
+#[derive(Component)]
+#[lifecycle]
+pub struct RetryQueue {
+    #[injected]
+    config: Injected<Config>,
+
+    #[injected]
+    channel: Injected<container::Channel>,
+
+    #[injected]
+    token: Injected<container::Token>,
+
+    #[injected]
+    job: Injected<container::Job>,
+
+    #[injected]
+    control: Injected<container::Control>,
+
+    #[injected]
+    health: Injected<container::Health>,
+
+    #[injected]
+    rate_limit: Injected<container::RateLimit>,
+
+    tx: Option<mpsc::Sender<()>>,
+    rx: Option<oneshot::Receiver<()>>,
+}
+
+#[async_trait::async_trait]
+impl ComponentLifecycle for RetryQueue {
+    async fn start(&mut self) {
+        let (stop_sender, rx) = oneshot::channel();
+        let (tx, mut stop_receiver) = mpsc::channel(1);
+
+        self.tx.replace(tx);
+        self.rx.replace(rx);
+
+        let config = self.config.clone();
+        let channel = self.channel.clone();
+        let token = self.token.clone();
+        let job = self.job.clone();
+        let control = self.control.clone();
+        let health = self.health.clone();
+        let rate_limit = self.rate_limit.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = stop_receiver.recv() => {
+                        break;
+                    }
+                    _ = sleep(config.retry_queue_interval()) => {}
+                };
+
+                // 토큰을 사용할 수 없거나 마도메 서버가 응답하지 않는다면 다시 시도해도 실패하므로 다음에 시도함
+                if !token.is_usable() || !health.is_healthy() {
+                    continue;
+                }
+
+                let ids = match error::error_ids().await {
+                    Ok(ids) => ids,
+                    Err(err) => {
+                        log::error!("RetryQueue: {err}");
+                        continue;
+                    }
+                };
+
+                let mut failed = Vec::new();
+
+                for id in ids {
+                    // cancel, skip한 작품은 다시 시도하지 않음
+                    if control.is_blocked(id) {
+                        continue;
+                    }
+
+                    match job.get(id).await {
+                        Some(x) if x.stage == Stage::Released => {
+                            log::info!("retry_queue;clear;id={id}");
+
+                            if let Err(err) = error::remove_error_file(id).await {
+                                log::error!("RetryQueue: {err}");
+                            }
+                        }
+                        // 페이지가 없는 작품은 다시 시도해도 release하지 않음
+                        Some(x) if x.stage != Stage::Discovered && x.total_page == 0 => {}
+                        Some(x) if x.failed && x.retries < config.retry_queue_max_attempts() => {
+                            failed.push(x);
+                        }
+                        _ => {}
+                    }
+                }
+
+                // 진행 중인 작업이 있다면 다음에 시도함
+                if failed.is_empty()
+                    || job
+                        .in_progress(|id| control.is_blocked(id), config.job_timeout())
+                        .await
+                    || !channel.is_idle()
+                {
+                    continue;
+                }
+
+                for x in failed {
+                    let id = x.id;
+
+                    if job.requeued(id).to(id, channel.err_tx()).await.is_none() {
+                        continue;
+                    }
+
+                    log::info!(
+                        "retry_queue;requeue;id={id};stage={:?};retries={}",
+                        x.stage,
+                        x.retries + 1
+                    );
+
+                    match x.stage {
+                        Stage::Discovered => {
+                            channel.id_tx().send(id).await.expect("closed id channel");
+                        }
+
+                        // 이미지와 썸네일은 다 올렸고 release만 실패함
+                        Stage::AboutSynced | Stage::Image if x.is_uploaded() => {
+                            channel
+                                .sync_tx()
+                                .send(SyncKind::Release(id))
+                                .await
+                                .expect("closed channel");
+                        }
+
+                        Stage::AboutSynced | Stage::Image => {
+                            if let Some(about) = retry(config.retry_policy(), "parse_about", || {
+                                parse_gallery(&rate_limit, id)
+                            })
+                            .to(id, channel.err_tx())
+                            .await
+                            {
+                                channel
+                                    .about_tx()
+                                    .send(about)
+                                    .await
+                                    .expect("closed channel");
+                            }
+                        }
+
+                        Stage::Released => {}
+                    }
+                }
+            }
+
+            log::debug!("shutdown_retry_queue");
+
+            stop_sender.send(()).unwrap();
+        });
+    }
+
+    async fn stop(&mut self) {
+        self.tx.take().unwrap().send(()).await.unwrap();
+
+        self.rx.take().unwrap().await.unwrap();
+    }
+}
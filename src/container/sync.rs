@@ -1,12 +1,22 @@
-use std::fmt::Debug;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    io,
+    sync::Arc,
+    time::Instant,
+};
 
 use bytes::Bytes;
+use futures::{stream::FuturesUnordered, StreamExt};
 use madome_sdk::api::{file, library};
 use sai::{Component, ComponentLifecycle, Injected};
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
 
 use crate::{
-    container::{self, ProgressKind},
+    cache::Staged,
+    config::Config,
+    container::{self, updater::content_hash, PipelineEvent, ProgressKind},
+    retry::{retry, Retryable},
     SendError,
 };
 
@@ -17,17 +27,65 @@ pub enum Error {
 
     #[error("{0}")]
     FileSdk(#[from] file::Error),
+
+    #[error("Job: {0}")]
+    Job(#[from] container::job::Error),
+
+    #[error("Crawler: {0}")]
+    Crawler(#[from] crawler::Error),
+
+    #[error("Cache: {0}")]
+    Cache(#[from] io::Error),
+
+    #[error("Not uploaded: pages={pages:?};thumbnail={thumbnail}")]
+    NotUploaded { pages: Vec<usize>, thumbnail: bool },
+
+    #[error("Can not verify: no pages recorded in the job")]
+    NotVerifiable,
 }
 
+impl Retryable for Error {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::LibrarySdk(err) => err.is_retryable(),
+            Self::FileSdk(err) => err.is_retryable(),
+            Self::Crawler(err) => err.is_retryable(),
+            Self::Job(_) | Self::Cache(_) | Self::NotUploaded { .. } | Self::NotVerifiable => false,
+        }
+    }
+}
+
+/// # Sync
+///
+/// 작품 정보와 이미지를 마도메에 올립니다.
+///
+/// 받은 작업들을 동시에 처리함 (`upload_concurrency`, `upload_concurrency_per_gallery`)
+///
+/// `SyncKind::Release`는 `Progress`가 작품의 모든 이미지가 올라간 걸 확인한 다음에 보내기 때문에 항상 작품의 마지막 작업임
 #[derive(Component)]
 #[lifecycle]
 pub struct Sync {
+    #[injected]
+    config: Injected<Config>,
+
     #[injected]
     channel: Injected<container::Channel>,
 
     #[injected]
     token: Injected<container::Token>,
 
+    #[injected]
+    job: Injected<container::Job>,
+
+    #[injected]
+    control: Injected<container::Control>,
+
+    #[injected]
+    health: Injected<container::Health>,
+
+    #[injected]
+    rate_limit: Injected<container::RateLimit>,
+
     tx: Option<mpsc::Sender<()>>,
     rx: Option<oneshot::Receiver<()>>,
 }
@@ -41,81 +99,115 @@ impl ComponentLifecycle for Sync {
         self.tx.replace(tx);
         self.rx.replace(rx);
 
+        let config = self.config.clone();
         let channel = self.channel.clone();
         let token = self.token.clone();
+        let job = self.job.clone();
+        let control = self.control.clone();
+        let health = self.health.clone();
+        let rate_limit = self.rate_limit.clone();
 
         tokio::spawn(async move {
+            let config = &config;
+            let channel = &channel;
             let token = &token;
+            let job = &job;
+            let health = &health;
+            let rate_limit = &rate_limit;
+
+            // 동시에 처리 중인 작업들, `upload_concurrency`를 넘지 않도록 받음
+            let mut running = FuturesUnordered::new();
+            // 작품 별로 동시에 올리는 이미지 수를 제한함
+            let mut galleries = HashMap::<u32, Arc<Semaphore>>::new();
+            // 작품의 permit을 기다리는 이미지들, `running`에 넣지 않아서 한 작품이 `upload_concurrency`를 다 쓰지 않음
+            //
+            // 이미지는 `sync_queue_mib`만큼만 받아둘 수 있기 때문에 계속 쌓이지 않음
+            let mut waiting = FuturesUnordered::new();
 
             loop {
+                let can_run = token.is_usable()
+                    && health.is_healthy()
+                    && running.len() < config.upload_concurrency();
+
                 let received = tokio::select! {
                     _ = stop_receiver.recv() => {
                         break;
                     }
-                    received = channel.sync_recv() => {
+                    Some(id) = running.next(), if !running.is_empty() => {
+                        // 기다리거나 올리는 이미지가 없다면 `Release`를 받지 못 한 작품도 지움
+                        if matches!(galleries.get(&id), Some(x) if Arc::strong_count(x) == 1) {
+                            galleries.remove(&id);
+                        }
+
+                        continue;
+                    }
+                    Some((received, permit)) = waiting.next(), if can_run && !waiting.is_empty() => {
+                        running.push(process(
+                            config, channel, token, job, health, rate_limit, received, Some(permit),
+                        ));
+                        continue;
+                    }
+                    // 토큰을 사용할 수 없다면 새로 발급 받을 때까지 받지 않음
+                    _ = token.wait_usable(), if !token.is_usable() => {
+                        continue;
+                    }
+                    // 마도메 서버가 응답하지 않는다면 응답할 때까지 받지 않음
+                    _ = health.wait_healthy(), if !health.is_healthy() => {
+                        continue;
+                    }
+                    received = channel.sync_recv(), if can_run => {
                         received
                     }
                 };
 
-                match received {
-                    // TODO: 이미지를 업로드 하기 전에 작품 정보를 업로드 하는데
-                    // library서버에서 해당 작품이 이미지 업로드가 된 작품인지 아닌지를 구별할 방법이 필요함
-                    //
-                    // 처음에 올릴 때는 pre-release 같은 느낌으로 외부에 변경을 덜 주는 방식으로 library 서버에서 변경하고,
-                    // 이후에 이미지 업로드가 다 됐따 하면 release하는 방식
-                    //
-                    // release 하는 도중에도 에러가 날 수 있으니까 이것도 저장해놨따가 아무것도 안할때 틈틈이 시도
-                    SyncKind::About(about) => {
-                        log::info!("sync_about;id={}", about.id);
-
-                        let r = sync_about(token, &about)
-                            .to(about.id, channel.err_tx())
-                            .await
-                            .is_some();
+                if control.is_blocked(received.id()) {
+                    log::info!("sync;blocked;{received:?}");
+                    galleries.remove(&received.id());
+                    continue;
+                }
 
-                        if r {
-                            // log::debug!("sync_about;send_about");
-                            channel.about_tx().send(about).await.unwrap();
-                        }
-                    }
+                match &received {
+                    SyncKind::Image(id, ..) => {
+                        let gallery = galleries
+                            .entry(*id)
+                            .or_insert_with(|| {
+                                Arc::new(Semaphore::new(config.upload_concurrency_per_gallery()))
+                            })
+                            .clone();
 
-                    SyncKind::Image(id, page, total_page, image, buf) => match image.kind() {
-                        crawler::image::ImageKind::Thumbnail => {
-                            log::info!("sync_thumbnail;id={id}");
+                        waiting.push(async move {
+                            let permit = gallery.acquire_owned().await.unwrap();
 
-                            let _r = sync_thumbnail(token, id, image, buf)
-                                .too(id, 0, total_page, channel.err_tx())
-                                .await
-                                .is_some();
-                        }
-
-                        crawler::image::ImageKind::Original => {
-                            log::info!("sync_image;id={id};page={page}/{total_page}");
-
-                            let r = sync_image(token, id, page, image, buf)
-                                .too(id, page, total_page, channel.err_tx())
-                                .await
-                                .is_some();
-
-                            if r {
-                                // progress 갱신
-                                channel
-                                    .progress_tx()
-                                    .send(ProgressKind::Image(id, page, total_page))
-                                    .await
-                                    .unwrap()
-                            }
-                        }
-                    },
+                            (received, permit)
+                        });
 
+                        continue;
+                    }
+                    // 작품의 모든 이미지를 올린 다음에만 받음
                     SyncKind::Release(id) => {
-                        log::info!("release_book;id={id}");
-
-                        let _r = release_book(token, id)
-                            .to(id, channel.err_tx())
-                            .await
-                            .is_some();
+                        galleries.remove(id);
                     }
+                    SyncKind::About(_) => {}
+                }
+
+                running.push(process(
+                    config, channel, token, job, health, rate_limit, received, None,
+                ));
+            }
+
+            // 올리지 못 한 이미지는 다음에 시작할 때 `About`에서 이어서 진행함
+            if !waiting.is_empty() {
+                log::info!("sync;stop;waiting={}", waiting.len());
+            }
+
+            drop(waiting);
+
+            // 진행 중인 작업은 마저 끝냄
+            // 마도메 서버가 응답하지 않는다면 기다리지 않음, 다음에 시작할 때 이어서 진행함
+            tokio::select! {
+                _ = async { while running.next().await.is_some() {} } => {}
+                _ = health.wait_unhealthy() => {
+                    log::info!("sync;stop;unhealthy;in_progress={}", running.len());
                 }
             }
 
@@ -134,11 +226,26 @@ impl ComponentLifecycle for Sync {
 
 pub enum SyncKind {
     About(crawler::model::Gallery),
-    /// id, page, total_page, image, buf
-    Image(u32, usize, usize, crawler::image::Image, Bytes),
+    /// id, page, total_page, image, staged
+    Image(
+        u32,
+        usize,
+        usize,
+        crawler::image::Image,
+        container::StagedImage,
+    ),
     Release(u32),
 }
 
+impl SyncKind {
+    pub fn id(&self) -> u32 {
+        match self {
+            Self::About(x) => x.id,
+            Self::Image(id, ..) | Self::Release(id) => *id,
+        }
+    }
+}
+
 impl Debug for SyncKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let x = match self {
@@ -151,11 +258,184 @@ impl Debug for SyncKind {
     }
 }
 
+/// 처리한 작품의 id를 돌려줌
+#[allow(clippy::too_many_arguments)]
+async fn process(
+    config: &Config,
+    channel: &container::Channel,
+    token: &container::Token,
+    job: &container::Job,
+    health: &container::Health,
+    rate_limit: &container::RateLimit,
+    received: SyncKind,
+    // 작품의 permit, 올리는 동안 가지고 있음
+    _permit: Option<OwnedSemaphorePermit>,
+) -> u32 {
+    let id = received.id();
+
+    // 받은 다음에 circuit이 열렸다면 에러를 남기지 않도록 닫힐 때까지 기다림
+    health.wait_healthy().await;
+
+    match received {
+        // TODO: 이미지를 업로드 하기 전에 작품 정보를 업로드 하는데
+        // library서버에서 해당 작품이 이미지 업로드가 된 작품인지 아닌지를 구별할 방법이 필요함
+        //
+        // 처음에 올릴 때는 pre-release 같은 느낌으로 외부에 변경을 덜 주는 방식으로 library 서버에서 변경하고,
+        // 이후에 이미지 업로드가 다 됐따 하면 release하는 방식
+        //
+        // release 하는 도중에도 에러가 날 수 있으니까 이것도 저장해놨따가 아무것도 안할때 틈틈이 시도
+        SyncKind::About(about) => {
+            log::debug!("sync_about;id={}", about.id);
+
+            let started = Instant::now();
+
+            let r = retry(config.retry_policy(), "sync_about", || {
+                sync_about(config, token, rate_limit, &about)
+            })
+            .to(about.id, channel.err_tx())
+            .await
+            .is_some()
+                && job
+                    .about_synced(about.id, about.files.len(), content_hash(&about))
+                    .to(about.id, channel.err_tx())
+                    .await
+                    .is_some();
+
+            if r {
+                channel.send_event(PipelineEvent::AboutSynced {
+                    id: about.id,
+                    total_page: about.files.len(),
+                    elapsed_ms: started.elapsed().as_millis() as u64,
+                });
+
+                // log::debug!("sync_about;send_about");
+                channel.about_tx().send(about).await.unwrap();
+            }
+        }
+
+        SyncKind::Image(id, page, total_page, image, staged) => {
+            let started = Instant::now();
+
+            match image.kind() {
+                crawler::image::ImageKind::Thumbnail => {
+                    log::debug!("sync_thumbnail;id={id}");
+
+                    let r = retry(config.retry_policy(), "sync_thumbnail", || {
+                        sync_thumbnail(config, token, rate_limit, id, &image, &staged)
+                    })
+                    .too(id, 0, total_page, channel.err_tx())
+                    .await
+                    .is_some()
+                        && job
+                            .thumbnail_uploaded(id)
+                            .too(id, 0, total_page, channel.err_tx())
+                            .await
+                            .is_some();
+
+                    if r {
+                        // 올렸으니 저장해둔 이미지는 지움
+                        if let Err(err) = staged.remove().await {
+                            log::error!("Sync: {err}");
+                        }
+
+                        channel.send_event(PipelineEvent::PageUploaded {
+                            id,
+                            page: 0,
+                            total_page,
+                            bytes: staged.len(),
+                            elapsed_ms: started.elapsed().as_millis() as u64,
+                        });
+
+                        channel
+                            .progress_tx()
+                            .send(ProgressKind::Thumbnail(id, total_page))
+                            .await
+                            .unwrap()
+                    }
+                }
+
+                crawler::image::ImageKind::Original => {
+                    log::debug!("sync_image;id={id};page={page}/{total_page}");
+
+                    let r = retry(config.retry_policy(), "sync_image", || {
+                        sync_image(config, token, rate_limit, id, page, &image, &staged)
+                    })
+                    .too(id, page, total_page, channel.err_tx())
+                    .await
+                    .is_some()
+                        && job
+                            .page_uploaded(id, page, total_page)
+                            .too(id, page, total_page, channel.err_tx())
+                            .await
+                            .is_some();
+
+                    if r {
+                        // 올렸으니 저장해둔 이미지는 지움
+                        if let Err(err) = staged.remove().await {
+                            log::error!("Sync: {err}");
+                        }
+
+                        channel.send_event(PipelineEvent::PageUploaded {
+                            id,
+                            page,
+                            total_page,
+                            bytes: staged.len(),
+                            elapsed_ms: started.elapsed().as_millis() as u64,
+                        });
+
+                        // progress 갱신
+                        channel
+                            .progress_tx()
+                            .send(ProgressKind::Image(id, page, total_page))
+                            .await
+                            .unwrap()
+                    }
+                }
+            }
+        }
+
+        SyncKind::Release(id) => {
+            log::debug!("release_book;id={id}");
+
+            let started = Instant::now();
+
+            let verified = !config.release_verify()
+                || verify_uploaded(config, token, job, rate_limit, id)
+                    .to(id, channel.err_tx())
+                    .await
+                    .is_some();
+
+            let r = verified
+                && retry(config.retry_policy(), "release_book", || {
+                    release_book(config, token, rate_limit, id)
+                })
+                .to(id, channel.err_tx())
+                .await
+                .is_some();
+
+            if r {
+                let _r = job.released(id).to(id, channel.err_tx()).await;
+
+                channel.send_event(PipelineEvent::Released {
+                    id,
+                    elapsed_ms: started.elapsed().as_millis() as u64,
+                });
+            }
+        }
+    }
+
+    id
+}
+
 #[allow(clippy::await_holding_lock)]
 async fn sync_about(
+    config: &Config,
     token: &container::Token,
+    rate_limit: &container::RateLimit,
     about: &crawler::model::Gallery,
 ) -> Result<(), Error> {
+    rate_limit.library().await;
+
     let (_lock, token) = token.as_behavior();
 
     let tags = about
@@ -166,7 +446,7 @@ async fn sync_about(
         .collect::<Vec<_>>();
 
     library::add_book(
-        "https://beta.api.madome.app",
+        &config.library_url(),
         token,
         about.id,
         about.title.clone(),
@@ -183,42 +463,126 @@ async fn sync_about(
 
 #[allow(clippy::await_holding_lock)]
 async fn sync_image(
+    config: &Config,
     token: &container::Token,
+    rate_limit: &container::RateLimit,
     id: u32,
     page: usize,
-    image: crawler::image::Image,
-    buf: Bytes,
+    image: &crawler::image::Image,
+    staged: &Staged,
 ) -> Result<(), Error> {
+    let buf = read_staged(rate_limit, image, staged).await?;
+
+    rate_limit.upload(buf.len()).await;
+
     let (_lock, token) = token.as_behavior();
 
     let path = format!("image/library/{id}/{page}.{}", image.ext());
 
-    file::upload("https://beta.api.madome.app", token, path, buf).await?;
+    file::upload(&config.file_url(), token, path, buf).await?;
 
     Ok(())
 }
 
 #[allow(clippy::await_holding_lock)]
 async fn sync_thumbnail(
+    config: &Config,
     token: &container::Token,
+    rate_limit: &container::RateLimit,
     id: u32,
-    image: crawler::image::Image,
-    buf: Bytes,
+    image: &crawler::image::Image,
+    staged: &Staged,
 ) -> Result<(), Error> {
+    let buf = read_staged(rate_limit, image, staged).await?;
+
+    rate_limit.upload(buf.len()).await;
+
     let (_lock, token) = token.as_behavior();
 
     let path = format!("image/library/{id}/thumbnail.{}", image.ext());
 
-    file::upload("https://beta.api.madome.app", token, path, buf).await?;
+    file::upload(&config.file_url(), token, path, buf).await?;
 
     Ok(())
 }
 
+/// 저장해둔 이미지를 읽음
+///
+/// 지워졌다면 (`cache_max_age`보다 오래됨 등) 다시 받음
+async fn read_staged(
+    rate_limit: &container::RateLimit,
+    image: &crawler::image::Image,
+    staged: &Staged,
+) -> Result<Bytes, Error> {
+    match staged.read().await {
+        Ok(buf) => Ok(buf),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            log::warn!("sync;staged;{err};download again");
+
+            rate_limit.download().await;
+
+            let buf = image.download().await?;
+
+            rate_limit.downloaded(buf.len());
+
+            Ok(buf)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// 파일 서버에 작품의 모든 페이지와 썸네일이 있는지 확인함
+///
+/// 없는 페이지와 썸네일은 `Job`에서 지워서 다시 시도할 때 올리도록 함
+async fn verify_uploaded(
+    config: &Config,
+    token: &container::Token,
+    job: &container::Job,
+    rate_limit: &container::RateLimit,
+    id: u32,
+) -> Result<(), Error> {
+    // 작업이 없거나 페이지 수를 모른다면 확인하지 않고 release되지 않도록 함
+    let total_page = match job.get(id).await.map(|x| x.total_page) {
+        Some(total_page) if total_page > 0 => total_page,
+        _ => return Err(Error::NotVerifiable),
+    };
+
+    let (pages, thumbnail) = retry(config.retry_policy(), "verify_uploaded", || {
+        container::image::get_uploaded(config, token, rate_limit, id)
+    })
+    .await?;
+
+    let pages = pages.into_iter().collect::<HashSet<_>>();
+
+    let not_uploaded = (1..=total_page)
+        .filter(|page| !pages.contains(page))
+        .collect::<Vec<_>>();
+    let thumbnail = !thumbnail;
+
+    if not_uploaded.is_empty() && !thumbnail {
+        return Ok(());
+    }
+
+    job.not_uploaded(id, &not_uploaded, thumbnail).await?;
+
+    Err(Error::NotUploaded {
+        pages: not_uploaded,
+        thumbnail,
+    })
+}
+
 #[allow(clippy::await_holding_lock)]
-async fn release_book(token: &container::Token, id: u32) -> Result<(), Error> {
+async fn release_book(
+    config: &Config,
+    token: &container::Token,
+    rate_limit: &container::RateLimit,
+    id: u32,
+) -> Result<(), Error> {
+    rate_limit.library().await;
+
     let (_lock, token) = token.as_behavior();
 
-    library::release_book("https://beta.api.madome.app", token, id).await?;
+    library::release_book(&config.library_url(), token, id).await?;
 
     Ok(())
 }
@@ -10,13 +10,16 @@ use madome_sdk::api::{
 use parking_lot::{RwLock, RwLockReadGuard};
 use sai::{Component, ComponentLifecycle, Injected};
 use serde::{Deserialize, Serialize};
-use tokio::{
-    fs::File,
-    io::{AsyncReadExt, AsyncWriteExt},
-    sync::{mpsc, oneshot},
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::{
+    config::Config,
+    container::{self, PipelineEvent},
+    retry::{self, Retryable},
+    storage, SendError,
 };
 
-use crate::{container, SendError};
+const TOKEN_PATH: &str = "./.token.json";
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -25,17 +28,79 @@ pub enum Error {
 
     #[error("Auth Sdk: {0}")]
     AuthSdk(#[from] auth::Error),
+
+    #[error("Not issued token pair")]
+    NotIssued,
+
+    #[error("Expired refresh token, please run `sync login`")]
+    Expired,
+}
+
+impl Retryable for Error {
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::AuthSdk(err) => err.is_retryable(),
+            Self::Io(_) | Self::NotIssued | Self::Expired => false,
+        }
+    }
+}
+
+impl Error {
+    /// refresh token을 더 이상 사용할 수 없어서 `sync login`으로 새로 발급해야함
+    fn is_unauthorized(&self) -> bool {
+        match self {
+            Self::AuthSdk(err) => retry::is_unauthorized(err),
+            Self::Expired => true,
+            Self::Io(_) | Self::NotIssued => false,
+        }
+    }
+}
+
+/// 3시간마다 토큰을 갱신함
+const REFRESH_INTERVAL: i64 = 10800;
+
+/// 마지막으로 갱신한 지 7일이 지나면 refresh token을 사용할 수 없음
+fn refresh_token_lifetime() -> chrono::Duration {
+    chrono::Duration::days(7)
+}
+
+/// 사용할 수 없게 되기 하루 전부터 알림
+fn expiring_soon() -> chrono::Duration {
+    chrono::Duration::days(1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenState {
+    Valid,
+    Refreshing,
+    /// 갱신에 계속 실패하고 있고, 곧 사용할 수 없게 됨
+    ///
+    /// 일시적이지 않은 에러로 갱신에 실패했을 때도 알림 (계속 다시 갱신함)
+    ExpiringSoon,
+    /// `sync login`으로 새로 발급해야함
+    Expired,
+}
+
+impl TokenState {
+    pub fn is_usable(&self) -> bool {
+        *self != Self::Expired
+    }
 }
 
 #[derive(Component)]
 #[lifecycle]
 pub struct Token {
+    #[injected]
+    config: Injected<Config>,
+
     #[injected]
     channel: Injected<container::Channel>,
 
     inner: Option<Arc<TokenRwLock>>,
     // 사용 중일 때는 read(), 갱신 중일 때는 write()를 사용함
     lock: Option<Arc<RwLock<()>>>,
+    state: Option<Arc<watch::Sender<TokenState>>>,
 
     tx: Option<mpsc::Sender<()>>,
     rx: Option<oneshot::Receiver<()>>,
@@ -44,7 +109,11 @@ pub struct Token {
 #[async_trait::async_trait]
 impl ComponentLifecycle for Token {
     async fn start(&mut self) {
-        self.initialize().await.expect("initialize token");
+        if let Err(err) = self.initialize().await {
+            log::error!("token;{err}");
+            eprintln!("invalid token: {err}");
+            std::process::exit(1);
+        }
 
         let (stop_sender, a_rx) = oneshot::channel();
         let (b_tx, mut stop_receiver) = mpsc::channel(1);
@@ -52,37 +121,80 @@ impl ComponentLifecycle for Token {
         self.rx.replace(a_rx);
         self.tx.replace(b_tx);
 
+        let (state, _rx) = watch::channel(TokenState::Valid);
+        let state = Arc::new(state);
+
+        self.state.replace(state.clone());
+
         let inner = self.inner.clone().unwrap();
         let lock = self.lock.clone().unwrap();
+        let config = self.config.clone();
         let channel = self.channel.clone();
 
         tokio::spawn(async move {
+            let set_state = |x: TokenState| {
+                if state.send_replace(x) == x {
+                    return;
+                }
+
+                channel.send_event(PipelineEvent::Token { state: x });
+
+                if x == TokenState::Expired {
+                    channel
+                        .err_tx()
+                        .send((None, None, None, Error::Expired.into()));
+                }
+            };
+
             loop {
-                let now = Utc::now().timestamp();
-                let created_at = { *inner.created_at.read() };
+                // `sync login`으로 새로 발급한 토큰이 있다면 바로 사용함
+                match inner.reload().await {
+                    Ok(true) => {
+                        log::info!("token;reloaded");
+                        set_state(TokenState::Valid);
+                    }
+                    Ok(false) => {}
+                    Err(err) => log::error!("token;reload;{err}"),
+                }
 
-                // if now - created_at.timestamp() > 7days {} panic!(토큰 발급 필요함)
+                let age = Utc::now() - *inner.created_at.read();
+
+                if age > refresh_token_lifetime() {
+                    set_state(TokenState::Expired);
+                } else if age > chrono::Duration::seconds(REFRESH_INTERVAL)
+                    && *state.borrow() != TokenState::Expired
+                {
+                    set_state(TokenState::Refreshing);
 
-                if now - created_at.timestamp() > 10800 {
                     // 갱신 중에는 사용하지 못 하도록 함
                     let _lock = lock.write();
                     let token = &*inner;
 
-                    match refresh_token_pair(token).await {
+                    match refresh_token_pair(&config, token).await {
                         Ok(_) => {
-                            /* *inner.pair.write() = token.pair.read().clone();
-                             *inner.created_at.write() = *token.created_at.read(); */
+                            channel.send_event(PipelineEvent::TokenRefreshed { ok: true });
 
                             let _r = inner.sync().to(None, channel.err_tx()).await.is_some();
+
+                            set_state(TokenState::Valid);
                         }
+                        // refresh token이 거부된 게 아니라면 다음에 다시 갱신함
+                        // (auth 서버를 배포하는 중의 404, TLS 에러 등)
                         Err(err) => {
-                            // TODO: send stop signal?
-
-                            let _r = channel
-                                .err_tx()
-                                .send((None, None, None, err.into()))
-                                .await
-                                .unwrap();
+                            let next = if err.is_unauthorized() {
+                                TokenState::Expired
+                            } else if !err.is_retryable()
+                                || age > refresh_token_lifetime() - expiring_soon()
+                            {
+                                TokenState::ExpiringSoon
+                            } else {
+                                TokenState::Valid
+                            };
+
+                            channel.send_event(PipelineEvent::TokenRefreshed { ok: false });
+                            channel.err_tx().send((None, None, None, err.into()));
+
+                            set_state(next);
                         }
                     }
                 }
@@ -118,22 +230,41 @@ impl Token {
     async fn initialize(&mut self) -> io::Result<()> {
         self.lock.replace(Arc::new(RwLock::new(())));
 
-        if let Ok(mut f) = File::open("./.token.json").await {
-            let mut buf = Vec::new();
-            f.read_to_end(&mut buf).await?;
+        // 올바르지 않은 파일은 직접 확인해야 하므로 그대로 둠
+        let t = storage::read_json::<TokenJson>(TOKEN_PATH)
+            .await?
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{TOKEN_PATH}: please run `sync login` to issue the token"),
+                )
+            })?;
+
+        self.inner.replace(Arc::new(TokenRwLock {
+            pair: RwLock::new((t.access, t.refresh)),
+            created_at: RwLock::new(t.created_at),
+        }));
 
-            let t = serde_json::from_slice::<TokenJson>(&buf).unwrap();
+        Ok(())
+    }
 
-            self.inner.replace(Arc::new(TokenRwLock {
-                pair: RwLock::new((t.access, t.refresh)),
-                created_at: RwLock::new(t.created_at),
-            }));
-        } else {
-            // Token::new()
-            panic!("please write the `.token.json`")
-        }
+    pub fn state(&self) -> TokenState {
+        *self.state.as_ref().unwrap().borrow()
+    }
 
-        Ok(())
+    pub fn is_usable(&self) -> bool {
+        self.state().is_usable()
+    }
+
+    /// 토큰을 사용할 수 없다면 새로 발급 받을 때까지 기다림
+    pub async fn wait_usable(&self) {
+        let mut rx = self.state.as_ref().unwrap().subscribe();
+
+        while !rx.borrow().is_usable() {
+            if rx.changed().await.is_err() {
+                break;
+            }
+        }
     }
 
     /// lock_guard로 사용 중에 갱신 되는 것을 막음
@@ -159,6 +290,26 @@ pub struct TokenRwLock {
 }
 
 impl TokenRwLock {
+    fn empty() -> Self {
+        Self {
+            pair: RwLock::new((String::new(), String::new())),
+            created_at: RwLock::new(Utc::now()),
+        }
+    }
+
+    /// `.token.json`에 더 최근에 발급한 토큰이 있다면 바꾸고 `true`를 반환함
+    async fn reload(&self) -> io::Result<bool> {
+        let t = match storage::read_json::<TokenJson>(TOKEN_PATH).await? {
+            Some(t) if t.created_at > *self.created_at.read() => t,
+            _ => return Ok(false),
+        };
+
+        *self.pair.write() = (t.access, t.refresh);
+        *self.created_at.write() = t.created_at;
+
+        Ok(true)
+    }
+
     fn to_json(&self) -> TokenJson {
         let (access, refresh) = self.pair.read().clone();
         let created_at = *self.created_at.read();
@@ -173,8 +324,7 @@ impl TokenRwLock {
     async fn sync(&self) -> Result<(), Error> {
         let serialized = serde_json::to_string_pretty(&self.to_json()).unwrap();
 
-        let mut f = File::open("./.token.json").await?;
-        f.write_all(serialized.as_bytes()).await?;
+        storage::write(TOKEN_PATH, serialized).await?;
 
         Ok(())
     }
@@ -202,8 +352,43 @@ impl TokenBehavior for TokenRwLock {
     }
 }
 
-async fn refresh_token_pair(token: &dyn TokenBehavior) -> Result<(), Error> {
-    auth::refresh_token_pair("https://beta.api.madome.app", token).await?;
+/// `.token.json`이 있는지
+pub(crate) async fn exists() -> bool {
+    tokio::fs::metadata(TOKEN_PATH).await.is_ok()
+}
+
+/// `sync login`
+///
+/// 인증 코드를 이메일로 보냄
+pub(crate) async fn create_authcode(config: &Config, email: String) -> Result<(), Error> {
+    let token = TokenRwLock::empty();
+
+    auth::create_authcode(&config.auth_url(), &token, email).await?;
+
+    Ok(())
+}
+
+/// `sync login`
+///
+/// 인증 코드로 토큰을 발급 받아서 `.token.json`에 저장함
+pub(crate) async fn create_token_pair(
+    config: &Config,
+    email: String,
+    code: String,
+) -> Result<(), Error> {
+    let token = TokenRwLock::empty();
+
+    auth::create_token_pair(&config.auth_url(), &token, email, code).await?;
+
+    if token.pair.read().0.is_empty() {
+        return Err(Error::NotIssued);
+    }
+
+    token.sync().await
+}
+
+async fn refresh_token_pair(config: &Config, token: &dyn TokenBehavior) -> Result<(), Error> {
+    auth::refresh_token_pair(&config.auth_url(), token).await?;
 
     Ok(())
 }
@@ -0,0 +1,174 @@
+use chrono::Utc;
+use sai::{Component, ComponentLifecycle, Injected};
+use sha2::{Digest, Sha256};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::sleep,
+};
+
+use crate::{
+    config::Config,
+    container::{self, about::parse_gallery, SyncKind},
+    retry::retry,
+    SendError,
+};
+
+/// # Updater
+///
+/// release된 작품들 중에 오랫동안 확인하지 않은 작품의 정보를 다시 가져와서 바뀌었는지 확인합니다.
+///
+/// 제목, 태그, 파일 목록의 해시가 저장된 것과 다르다면 작품 정보를 다시 올리고, 바뀐 페이지만 다시 올린 다음에 release함
+///
+/// library 서버에 수정하는 api가 없어서 `add_book`으로 다시 올림 (이미 있는 작품을 덮어쓴다고 가정함)
+///
+/// 다시 올리다가 실패하면 에러를 남기고 `update_after`가 지난 다음에 다시 확인함
+#[derive(Component)]
+#[lifecycle]
+pub struct Updater {
+    #[injected]
+    config: Injected<Config>,
+
+    #[injected]
+    channel: Injected<container::Channel>,
+
+    #[injected]
+    token: Injected<container::Token>,
+
+    #[injected]
+    job: Injected<container::Job>,
+
+    #[injected]
+    control: Injected<container::Control>,
+
+    #[injected]
+    health: Injected<container::Health>,
+
+    #[injected]
+    rate_limit: Injected<container::RateLimit>,
+
+    tx: Option<mpsc::Sender<()>>,
+    rx: Option<oneshot::Receiver<()>>,
+}
+
+#[async_trait::async_trait]
+impl ComponentLifecycle for Updater {
+    async fn start(&mut self) {
+        let (stop_sender, rx) = oneshot::channel();
+        let (tx, mut stop_receiver) = mpsc::channel(1);
+
+        self.tx.replace(tx);
+        self.rx.replace(rx);
+
+        let config = self.config.clone();
+        let channel = self.channel.clone();
+        let token = self.token.clone();
+        let job = self.job.clone();
+        let control = self.control.clone();
+        let health = self.health.clone();
+        let rate_limit = self.rate_limit.clone();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = stop_receiver.recv() => {
+                        break;
+                    }
+                    _ = sleep(config.update_interval()) => {}
+                };
+
+                // 토큰을 사용할 수 없거나 마도메 서버가 응답하지 않는다면 다음에 확인함
+                if !token.is_usable() || !health.is_healthy() {
+                    continue;
+                }
+
+                let before = Utc::now() - config.update_after();
+                let stale = job.stale(before, config.update_batch()).await;
+
+                if !stale.is_empty() {
+                    log::info!("updater;stale={}", stale.len());
+                }
+
+                for x in stale {
+                    let id = x.id;
+
+                    if control.is_blocked(id) {
+                        continue;
+                    }
+
+                    let about = match retry(config.retry_policy(), "parse_about", || {
+                        parse_gallery(&rate_limit, id)
+                    })
+                    .to(id, channel.err_tx())
+                    .await
+                    {
+                        Some(about) => about,
+                        None => continue,
+                    };
+
+                    let hash = content_hash(&about);
+
+                    match x.hash {
+                        // 이전에 올린 작품 정보를 알 수 없기 때문에 다시 올리지 않음
+                        None => {
+                            let _r = job.hashed(id, hash).to(id, channel.err_tx()).await;
+                        }
+                        Some(prev) if prev != hash => {
+                            log::info!("updater;changed;id={id}");
+
+                            // 작품 정보를 다시 올리다가 실패하더라도 `update_after`가 지나기 전에는 다시 확인하지 않도록 먼저 저장함
+                            // 해시는 작품 정보를 올린 다음에 저장함
+                            let _r = job.checked(id).to(id, channel.err_tx()).await;
+
+                            channel
+                                .sync_tx()
+                                .send(SyncKind::About(about))
+                                .await
+                                .expect("closed channel");
+                        }
+                        Some(_) => {
+                            let _r = job.checked(id).to(id, channel.err_tx()).await;
+                        }
+                    }
+                }
+            }
+
+            log::debug!("shutdown_updater");
+
+            stop_sender.send(()).unwrap();
+        });
+    }
+
+    async fn stop(&mut self) {
+        self.tx.take().unwrap().send(()).await.unwrap();
+
+        self.rx.take().unwrap().await.unwrap();
+    }
+}
+
+/// 제목, 태그, 파일 목록의 해시
+pub(super) fn content_hash(about: &crawler::model::Gallery) -> String {
+    let mut tags = about
+        .tags
+        .iter()
+        .map(|tag| format!("{}:{}", tag.kind, tag.name))
+        .collect::<Vec<_>>();
+
+    tags.sort();
+
+    let mut hasher = Sha256::new();
+
+    hasher.update(about.title.as_bytes());
+    hasher.update([0]);
+
+    for tag in tags {
+        hasher.update(tag.as_bytes());
+        hasher.update([0]);
+    }
+
+    for file in &about.files {
+        hasher.update(file.hash.as_bytes());
+        hasher.update([0]);
+    }
+
+    format!("{:x}", hasher.finalize())
+}
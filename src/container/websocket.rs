@@ -1,39 +1,456 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashSet, net::SocketAddr};
 
 use async_tungstenite::{
     tokio::accept_async,
     tungstenite::{self, Message},
 };
-use futures::StreamExt;
-use parking_lot::Mutex;
-use sai::{Component, ComponentLifecycle};
+use chrono::{DateTime, Utc};
+use futures::{SinkExt, StreamExt};
+use sai::{Component, ComponentLifecycle, Injected};
+use serde::{Deserialize, Serialize};
 use tokio::{
-    net::{unix::SocketAddr, TcpListener, TcpStream},
-    sync::mpsc,
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, mpsc, oneshot, watch},
 };
 
+use crate::{
+    config::Config,
+    container::{
+        self,
+        error::ErrorJson,
+        job::{JobJson, Stage},
+        PipelineEvent, QueueStats,
+    },
+};
+
+/// # WebSocket
+///
+/// 작품 별 진행 상황과 에러를 실시간으로 클라이언트에게 보냅니다.
+///
+/// 보내는 이벤트는 `PipelineEvent`
+///
+/// 1. 클라이언트가 연결하면 모든 이벤트를 보냄
+/// 2. 클라이언트가 작품을 구독하면 구독한 작품의 이벤트만 보냄
+/// 3. 토큰이나 마도메 서버의 상태가 바뀌면 구독과 상관 없이 보냄
+///
+/// ```json
+/// { "type": "token", "state": "expired" }
+/// { "type": "health", "circuit": "open", "failed": ["library", "file"] }
+/// ```
+///
+/// ```json
+/// { "type": "subscribe", "id": 1234 }
+/// { "type": "unsubscribe", "id": 1234 }
+/// { "type": "subscribe_all" }
+/// ```
+///
+/// 작품의 에러 기록과 진행 상황을 요청할 수도 있음
+///
+/// 클라이언트에서 어떤 작품이 `release: false`일 때 진행 중인지 실패한 건지 구별하기 위함
+///
+/// ```json
+/// { "type": "lookup", "id": 1234 }
+/// ```
+///
+/// 채널마다 쌓여있는 수를 요청할 수도 있음 (`Channel::queue_stats`)
+///
+/// ```json
+/// { "type": "queues" }
+/// ```
+///
+/// 실행 중인 인스턴스를 제어할 수도 있음 (`Control`)
+///
+/// `ADMIN_TOKEN`과 같은 `token`을 같이 보내야함, `ADMIN_TOKEN`이 설정되어 있지 않다면 command를 받지 않음
+///
+/// ```json
+/// { "type": "command", "command": "pause", "token": "..." }
+/// { "type": "command", "command": "resume", "token": "..." }
+/// { "type": "command", "command": "abort", "token": "..." }
+/// { "type": "command", "command": "cancel", "id": 1234, "token": "..." }
+/// { "type": "command", "command": "skip", "id": 1234, "token": "..." }
+/// { "type": "command", "command": "resync", "id": 1234, "token": "..." }
+/// { "type": "command", "command": "reload_config", "token": "..." }
+/// ```
 #[derive(Component)]
 #[lifecycle]
-pub struct WebSocket {}
+pub struct WebSocket {
+    #[injected]
+    config: Injected<Config>,
+
+    #[injected]
+    channel: Injected<container::Channel>,
+
+    #[injected]
+    job: Injected<container::Job>,
+
+    #[injected]
+    control: Injected<container::Control>,
+
+    tx: Option<mpsc::Sender<()>>,
+    rx: Option<oneshot::Receiver<()>>,
+}
 
 #[async_trait::async_trait]
 impl ComponentLifecycle for WebSocket {
     async fn start(&mut self) {
-        async fn handle_connection(
-            peers: Arc<Mutex<HashMap<SocketAddr, mpsc::Sender<Message>>>>,
-            peer: SocketAddr,
-            stream: TcpStream,
-        ) -> tungstenite::Result<()> {
-            let mut stream = accept_async(stream).await.unwrap();
+        let (stop_sender, rx) = oneshot::channel();
+        let (tx, mut stop_receiver) = mpsc::channel(1);
+
+        self.tx.replace(tx);
+        self.rx.replace(rx);
+
+        let addr = self.config.websocket_addr();
+        let listener = TcpListener::bind(addr)
+            .await
+            .expect("bind websocket address");
+
+        log::info!("websocket;listen={addr}");
+
+        let config = self.config.clone();
+        let channel = self.channel.clone();
+        let job = self.job.clone();
+        let control = self.control.clone();
+
+        tokio::spawn(async move {
+            // 연결된 클라이언트들에게 멈추라고 알려줌
+            let (shutdown_tx, shutdown_rx) = watch::channel(());
+
+            loop {
+                let (stream, peer) = tokio::select! {
+                    _ = stop_receiver.recv() => {
+                        break;
+                    }
+                    accepted = listener.accept() => match accepted {
+                        Ok(accepted) => accepted,
+                        Err(err) => {
+                            log::error!("WebSocket: {err}");
+                            continue;
+                        }
+                    }
+                };
+
+                let events = channel.subscribe_event();
+                let shutdown = shutdown_rx.clone();
+                let context = Context {
+                    config: config.clone(),
+                    channel: channel.clone(),
+                    job: job.clone(),
+                    control: control.clone(),
+                };
+
+                tokio::spawn(async move {
+                    let r = handle_connection(peer, stream, &context, events, shutdown).await;
+
+                    if let Err(err) = r {
+                        log::debug!("websocket;peer={peer};{err}");
+                    }
+                });
+            }
+
+            let _r = shutdown_tx.send(());
+
+            log::debug!("shutdown_websocket");
+
+            stop_sender.send(()).unwrap();
+        });
+    }
+
+    async fn stop(&mut self) {
+        self.tx.take().unwrap().send(()).await.unwrap();
+
+        self.rx.take().unwrap().await.unwrap();
+    }
+}
+
+async fn handle_connection(
+    peer: SocketAddr,
+    stream: TcpStream,
+    context: &Context,
+    mut events: broadcast::Receiver<PipelineEvent>,
+    mut shutdown: watch::Receiver<()>,
+) -> tungstenite::Result<()> {
+    let stream = accept_async(stream).await?;
+
+    let (mut outgoing, mut incoming) = stream.split();
+
+    log::info!("websocket;connected;peer={peer}");
+
+    let mut subscription = Subscription::All;
+
+    loop {
+        tokio::select! {
+            _ = shutdown.changed() => {
+                break;
+            }
+
+            message = incoming.next() => {
+                let message = match message {
+                    Some(message) => message?,
+                    None => break,
+                };
+
+                let text = match message {
+                    Message::Text(text) => text,
+                    Message::Close(_) => break,
+                    _ => continue,
+                };
+
+                let response = match serde_json::from_str::<Request>(&text) {
+                    Ok(Request::Lookup { id }) => lookup(&context.job, id).await,
+                    Ok(Request::Queues) => Response::Queues {
+                        queues: context.channel.queue_stats(),
+                    },
+                    Ok(Request::Command(command)) => execute(context, command).await,
+                    Ok(request) => {
+                        subscription.apply(request);
+                        continue;
+                    }
+                    Err(err) => Response::InvalidRequest {
+                        message: err.to_string(),
+                    },
+                };
+
+                let serialized = serde_json::to_string(&response).unwrap();
+
+                outgoing.send(Message::Text(serialized)).await?;
+            }
+
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        log::warn!("websocket;peer={peer};lagged={count}");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
 
-            let (outgoing, incoming) = stream.split();
+                if subscription.contains(&event) {
+                    let serialized = serde_json::to_string(&event).unwrap();
 
+                    outgoing.send(Message::Text(serialized)).await?;
+                }
+            }
+        }
+    }
+
+    log::info!("websocket;disconnected;peer={peer}");
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Subscribe { id: u32 },
+    Unsubscribe { id: u32 },
+    SubscribeAll,
+    Lookup { id: u32 },
+    Queues,
+    Command(Command),
+}
+
+#[derive(Deserialize)]
+struct Command {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(flatten)]
+    kind: CommandKind,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum CommandKind {
+    Pause,
+    Resume,
+    Abort,
+    Cancel { id: u32 },
+    Skip { id: u32 },
+    Resync { id: u32 },
+    ReloadConfig,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Response {
+    InvalidRequest {
+        message: String,
+    },
+    Lookup {
+        id: u32,
+        /// 에러 기록이 없다면 null
+        errors: Option<Vec<ErrorJson>>,
+        /// 진행 중이 아니라면 null
+        progress: Option<InFlight>,
+    },
+    Queues {
+        queues: Vec<QueueStats>,
+    },
+    Command {
+        ok: bool,
+        message: Option<String>,
+    },
+}
+
+struct Context {
+    config: Injected<Config>,
+    channel: Injected<container::Channel>,
+    job: Injected<container::Job>,
+    control: Injected<container::Control>,
+}
+
+#[derive(Serialize)]
+struct InFlight {
+    stage: Stage,
+    uploaded: usize,
+    total_page: usize,
+    thumbnail: bool,
+    failed: bool,
+    updated_at: DateTime<Utc>,
+}
+
+impl From<JobJson> for InFlight {
+    fn from(job: JobJson) -> Self {
+        Self {
+            stage: job.stage,
+            uploaded: job.pages.len(),
+            total_page: job.total_page,
+            thumbnail: job.thumbnail,
+            failed: job.failed,
+            updated_at: job.updated_at,
+        }
+    }
+}
+
+async fn lookup(job: &container::Job, id: u32) -> Response {
+    let errors = ErrorJson::read(id).await.unwrap_or_else(|err| {
+        log::error!("WebSocket: lookup;id={id};{err}");
+        None
+    });
+
+    let progress = job
+        .get(id)
+        .await
+        .filter(|x| x.stage != Stage::Released)
+        .map(InFlight::from);
+
+    Response::Lookup {
+        id,
+        errors,
+        progress,
+    }
+}
+
+async fn execute(context: &Context, command: Command) -> Response {
+    // 설정을 다시 읽으면 바로 적용되도록 매번 읽음
+    let admin_token = match context.config.admin_token() {
+        Some(admin_token) => admin_token,
+        None => {
+            return Response::Command {
+                ok: false,
+                message: Some("commands are disabled".to_string()),
+            }
+        }
+    };
+
+    let authorized = command
+        .token
+        .as_ref()
+        .map(|token| constant_time_eq(token.as_bytes(), admin_token.as_bytes()))
+        .unwrap_or(false);
+
+    if !authorized {
+        log::warn!("websocket;command={:?};invalid token", command.kind);
+
+        return Response::Command {
+            ok: false,
+            message: Some("invalid token".to_string()),
+        };
+    }
+
+    log::info!("websocket;command={:?}", command.kind);
+
+    let control = &context.control;
+
+    let r = match command.kind {
+        CommandKind::Pause => {
+            control.pause();
+            Ok(())
+        }
+        CommandKind::Resume => {
+            control.resume();
             Ok(())
-            // 1. 클라이언트에서 구독(각 작품)을 하면 주는 방식
-            // 2. 클라이언트에서 연결을 하면 전부 쏴주는 방식
-            // 2-1. 클라이언트에서 이거 좀 주세요 하고 요청을 할 수도 있나?
         }
+        CommandKind::Abort => {
+            control.abort();
+            Ok(())
+        }
+        CommandKind::Cancel { id } => {
+            control.cancel(id);
+            Ok(())
+        }
+        CommandKind::Skip { id } => {
+            control.skip(id);
+            Ok(())
+        }
+        CommandKind::Resync { id } => control.resync(id).await.map_err(|err| err.to_string()),
+        CommandKind::ReloadConfig => context.config.reload().map_err(|err| err.to_string()),
+    };
+
+    match r {
+        Ok(()) => Response::Command {
+            ok: true,
+            message: None,
+        },
+        Err(err) => Response::Command {
+            ok: false,
+            message: Some(err),
+        },
+    }
+}
+
+enum Subscription {
+    All,
+    Ids(HashSet<u32>),
+}
+
+impl Subscription {
+    fn apply(&mut self, request: Request) {
+        match (request, &mut *self) {
+            (Request::Subscribe { id }, Self::All) => {
+                *self = Self::Ids(HashSet::from([id]));
+            }
+            (Request::Subscribe { id }, Self::Ids(ids)) => {
+                ids.insert(id);
+            }
+            (Request::Unsubscribe { id }, Self::Ids(ids)) => {
+                ids.remove(&id);
+            }
+            (Request::Unsubscribe { .. }, Self::All) => {}
+            (Request::SubscribeAll, _) => {
+                *self = Self::All;
+            }
+            (Request::Lookup { .. } | Request::Queues | Request::Command(_), _) => {}
+        }
+    }
+
+    fn contains(&self, event: &PipelineEvent) -> bool {
+        match self {
+            Self::All => true,
+            // 작품과 상관 없는 이벤트(토큰 상태 등)는 항상 보냄
+            Self::Ids(ids) => match event.id() {
+                Some(id) => ids.contains(&id),
+                None => true,
+            },
+        }
+    }
+}
+
+/// 토큰을 비교하는 시간으로 일치하는 길이를 알 수 없도록 끝까지 비교함
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
     }
 
-    async fn stop(&mut self) {}
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
 }
@@ -1,8 +1,6 @@
 use std::future::Future;
 
-use tokio::sync::mpsc;
-
-use crate::container;
+use crate::container::{self, ErrorSender};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -20,6 +18,12 @@ pub enum Error {
 
     #[error("Nozomi: {0}")]
     Nozomi(#[from] container::nozomi::Error),
+
+    #[error("Job: {0}")]
+    Job(#[from] container::job::Error),
+
+    #[error("Health: {0}")]
+    Health(#[from] container::health::Error),
     /* #[error("Auth Sdk: {0}")]
     AuthSdk(#[from] auth::Error),
 
@@ -36,18 +40,14 @@ pub enum Error {
 
 #[async_trait::async_trait]
 pub trait SendError<T> {
-    async fn to(
-        self,
-        id: impl Into<Option<u32>> + Send,
-        tx: mpsc::Sender<(Option<u32>, Option<usize>, Option<usize>, Error)>,
-    ) -> Option<T>;
+    async fn to(self, id: impl Into<Option<u32>> + Send, tx: ErrorSender) -> Option<T>;
 
     async fn too(
         self,
         id: impl Into<Option<u32>> + Send,
         page: impl Into<Option<usize>> + Send,
         total_page: impl Into<Option<usize>> + Send,
-        tx: mpsc::Sender<(Option<u32>, Option<usize>, Option<usize>, Error)>,
+        tx: ErrorSender,
     ) -> Option<T>;
 }
 
@@ -58,11 +58,7 @@ where
     E: Into<Error> + Send,
     F: Future<Output = Result<T, E>> + Send,
 {
-    async fn to(
-        self,
-        id: impl Into<Option<u32>> + Send,
-        tx: mpsc::Sender<(Option<u32>, Option<usize>, Option<usize>, Error)>,
-    ) -> Option<T> {
+    async fn to(self, id: impl Into<Option<u32>> + Send, tx: ErrorSender) -> Option<T> {
         self.too(id, None, None, tx).await
     }
 
@@ -71,14 +67,12 @@ where
         id: impl Into<Option<u32>> + Send,
         page: impl Into<Option<usize>> + Send,
         total_page: impl Into<Option<usize>> + Send,
-        tx: mpsc::Sender<(Option<u32>, Option<usize>, Option<usize>, Error)>,
+        tx: ErrorSender,
     ) -> Option<T> {
         match self.await {
             Ok(r) => Some(r),
             Err(err) => {
-                tx.send((id.into(), page.into(), total_page.into(), err.into()))
-                    .await
-                    .unwrap();
+                tx.send((id.into(), page.into(), total_page.into(), err.into()));
                 None
             }
         }
@@ -1,7 +1,11 @@
+mod cache;
 mod config;
 mod container;
 pub mod error;
+pub mod login;
 mod registry;
+mod retry;
+mod storage;
 
 pub use error::{Error, SendError};
 pub use registry::RootRegistry;
@@ -0,0 +1,63 @@
+use std::io::{self, BufRead, Write};
+
+use crate::{
+    config::{self, Config},
+    container::token,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Config: {0}")]
+    Config(#[from] config::Error),
+
+    #[error("Token: {0}")]
+    Token(#[from] token::Error),
+
+    #[error("Io: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Cancelled")]
+    Cancelled,
+}
+
+/// # sync login
+///
+/// 이메일로 받은 인증 코드로 토큰을 발급 받아서 `.token.json`을 만듭니다.
+///
+/// ```sh
+/// sync login
+/// ```
+pub async fn run() -> Result<(), Error> {
+    let config = Config::load()?;
+
+    if token::exists().await {
+        let answer = prompt("`.token.json` already exists. overwrite? [y/N]")?;
+
+        if !answer.eq_ignore_ascii_case("y") {
+            return Err(Error::Cancelled);
+        }
+    }
+
+    let email = prompt("email")?;
+
+    token::create_authcode(&config, email.clone()).await?;
+
+    let code = prompt("code (check your email)")?;
+
+    token::create_token_pair(&config, email, code).await?;
+
+    Ok(())
+}
+
+fn prompt(label: &str) -> io::Result<String> {
+    let mut stdout = io::stdout();
+
+    write!(stdout, "{label}: ")?;
+    stdout.flush()?;
+
+    let mut line = String::new();
+
+    io::stdin().lock().read_line(&mut line)?;
+
+    Ok(line.trim().to_string())
+}
@@ -49,6 +49,18 @@ async fn main() {
         LevelFilter::Debug,
     ));
 
+    if std::env::args().nth(1).as_deref() == Some("login") {
+        match sync::login::run().await {
+            Ok(()) => println!("wrote `.token.json`"),
+            Err(err) => {
+                eprintln!("login: {err}");
+                std::process::exit(1);
+            }
+        }
+
+        return;
+    }
+
     let mut system = System::<RootRegistry>::new();
 
     system.start().await;
@@ -87,10 +99,7 @@ async fn main() {
     // 실패한 작품을 다시 받는 건 수동으로 해야함
     //
 
-    // TODO: 토큰 최초 발급을 대화형으로 만들자
     // TODO: library 서버에 ws 구현
-    // TODO: madome health check (loop로 감싼 다음 주기적으로 마도메 헬스체크해서 일정 횟수이상 안되면 꺼지게 tokio::select에 넣자)
-    // 근데 그렇게 하면 내부 동작도 멈춰야하는데
     // TODO: error handle
     // TODO: 새 파일서버를 사용하게 된다면 madome-sdk에서 file api의 url을 수정해야함
     // TODO: 로그 파일에서 에러를 찾아내는 유틸
@@ -8,14 +8,24 @@ mod root_registry {
     component_registry!(
         RootRegistry,
         [
-            container::Token,
             container::Token,
             container::About,
+            container::Backfill,
             container::Channel,
+            container::Control,
+            container::ErrorManager,
+            container::Health,
             container::Image,
+            container::Job,
+            container::Logger,
+            container::Metrics,
             container::Nozomi,
             container::Sync,
+            container::WebSocket,
             container::Progress,
+            container::RateLimit,
+            container::RetryQueue,
+            container::Updater,
             Config
         ]
     );
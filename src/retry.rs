@@ -0,0 +1,304 @@
+use std::{fmt::Display, future::Future, io, time::Duration};
+
+use madome_sdk::api::{auth, file, library};
+use rand::Rng;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// 처음 시도를 포함한 최대 시도 횟수
+    pub max_attempts: usize,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    /// exponential backoff with full jitter
+    ///
+    /// `attempt`는 1부터 시작함
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let exp = 2_u32.saturating_pow(attempt.saturating_sub(1) as u32);
+        let max = self.base_delay.saturating_mul(exp).min(self.max_delay);
+
+        let millis = rand::thread_rng().gen_range(0..=max.as_millis() as u64);
+
+        Duration::from_millis(millis)
+    }
+}
+
+/// 다시 시도하면 성공할 수도 있는 에러인지
+pub trait Retryable {
+    fn is_retryable(&self) -> bool;
+}
+
+/// 다시 시도해도 되는 에러라면 `policy`에 따라서 다시 시도함
+///
+/// 마지막 에러는 그대로 반환하니까 `SendError`로 `ErrorManager`에 보내면 됨
+pub async fn retry<T, E, F, Fut>(policy: RetryPolicy, label: &str, mut f: F) -> Result<T, E>
+where
+    E: Retryable + Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 1;
+
+    loop {
+        match f().await {
+            Ok(r) => return Ok(r),
+            Err(err) if attempt < policy.max_attempts && err.is_retryable() => {
+                let delay = policy.delay(attempt);
+
+                log::warn!(
+                    "retry;{label};attempt={attempt}/{};delay={}ms;{err}",
+                    policy.max_attempts,
+                    delay.as_millis()
+                );
+
+                tokio::time::sleep(delay).await;
+
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// 네트워크 에러, 타임아웃, 429, 5xx만 다시 시도함
+///
+/// 1. `source()`를 따라가서 `io::Error`가 있다면 `io::ErrorKind`로 구분함
+/// 2. sdk와 crawler의 에러는 상태 코드를 일정하게 노출하지 않아서 에러 메세지로 구분함
+///
+/// 에러 메세지의 상태 코드는 `status`, `code`, `http` 바로 뒤에 있을 때만 봄 (작품 번호, 페이지 번호 등과 구분하기 위함)
+fn is_transient(err: &(dyn std::error::Error + 'static)) -> bool {
+    const MESSAGES: [&str; 13] = [
+        "timed out",
+        "timeout",
+        "connection",
+        "broken pipe",
+        "reset by peer",
+        "unexpected eof",
+        "dns error",
+        "temporarily unavailable",
+        "too many requests",
+        "internal server error",
+        "bad gateway",
+        "service unavailable",
+        "gateway timeout",
+    ];
+    const STATUS_CODES: [u16; 5] = [429, 500, 502, 503, 504];
+
+    if let Some(err) = find_io_error(err) {
+        return matches!(
+            err.kind(),
+            io::ErrorKind::TimedOut
+                | io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::NotConnected
+                | io::ErrorKind::BrokenPipe
+                | io::ErrorKind::UnexpectedEof
+                | io::ErrorKind::Interrupted
+                | io::ErrorKind::WouldBlock
+        );
+    }
+
+    let msg = err.to_string().to_lowercase();
+
+    MESSAGES.iter().any(|x| msg.contains(x))
+        || status_codes(&msg).any(|code| STATUS_CODES.contains(&code))
+}
+
+/// 다시 로그인해야 하는 에러인지 (401)
+///
+/// `is_transient`와 같은 이유로 에러 메세지로 구분함
+pub fn is_unauthorized(err: &(dyn std::error::Error + 'static)) -> bool {
+    let msg = err.to_string().to_lowercase();
+
+    msg.contains("unauthorized") || status_codes(&msg).any(|code| code == 401)
+}
+
+fn find_io_error<'a>(err: &'a (dyn std::error::Error + 'static)) -> Option<&'a io::Error> {
+    let mut next = Some(err);
+
+    while let Some(err) = next {
+        if let Some(err) = err.downcast_ref::<io::Error>() {
+            return Some(err);
+        }
+
+        next = err.source();
+    }
+
+    None
+}
+
+/// `status: 503`, `status code 503`, `http 503`, `HTTP/1.1 503` 등에서 상태 코드
+fn status_codes(msg: &str) -> impl Iterator<Item = u16> + '_ {
+    const PREFIXES: [&str; 3] = ["status", "code", "http"];
+
+    let words = msg
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>();
+
+    (0..words.len()).filter_map(move |i| {
+        let code = words[i];
+
+        if code.len() != 3 {
+            return None;
+        }
+
+        // `HTTP/1.1`의 버전은 건너뜀
+        let prefix = words[..i]
+            .iter()
+            .rev()
+            .find(|x| !(x.len() == 1 && x.chars().all(|c| c.is_ascii_digit())));
+
+        match prefix {
+            Some(prefix) if PREFIXES.contains(prefix) => code.parse().ok(),
+            _ => None,
+        }
+    })
+}
+
+impl Retryable for crawler::Error {
+    fn is_retryable(&self) -> bool {
+        is_transient(self)
+    }
+}
+
+impl Retryable for library::Error {
+    fn is_retryable(&self) -> bool {
+        is_transient(self)
+    }
+}
+
+impl Retryable for auth::Error {
+    fn is_retryable(&self) -> bool {
+        is_transient(self)
+    }
+}
+
+impl Retryable for file::Error {
+    fn is_retryable(&self) -> bool {
+        is_transient(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fmt, io, time::Duration};
+
+    use super::*;
+
+    /// `io::Error`가 아닌 sdk, crawler의 에러
+    #[derive(Debug)]
+    struct Message(&'static str);
+
+    impl fmt::Display for Message {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl std::error::Error for Message {}
+
+    /// `source()`로 `io::Error`를 가지고 있는 에러
+    #[derive(Debug, thiserror::Error)]
+    #[error("request failed")]
+    struct Wrapped(#[source] io::Error);
+
+    fn transient(msg: &'static str) -> bool {
+        is_transient(&Message(msg))
+    }
+
+    #[test]
+    fn io_kinds() {
+        let retryable = [
+            io::ErrorKind::TimedOut,
+            io::ErrorKind::ConnectionReset,
+            io::ErrorKind::ConnectionRefused,
+            io::ErrorKind::BrokenPipe,
+            io::ErrorKind::UnexpectedEof,
+        ];
+
+        for kind in retryable {
+            assert!(is_transient(&io::Error::from(kind)), "{kind:?}");
+        }
+
+        let permanent = [
+            io::ErrorKind::NotFound,
+            io::ErrorKind::PermissionDenied,
+            io::ErrorKind::InvalidData,
+        ];
+
+        for kind in permanent {
+            assert!(!is_transient(&io::Error::from(kind)), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn io_kind_in_source() {
+        let err = Wrapped(io::Error::from(io::ErrorKind::ConnectionReset));
+
+        assert!(is_transient(&err));
+
+        // 메세지에 상태 코드가 있어도 `io::ErrorKind`로 구분함
+        let err = Wrapped(io::Error::new(io::ErrorKind::NotFound, "status 503"));
+
+        assert!(!is_transient(&err));
+    }
+
+    #[test]
+    fn status_code_after_prefix() {
+        assert!(transient("status 503"));
+        assert!(transient("Status: 502"));
+        assert!(transient("code 429"));
+        assert!(transient("status code 500"));
+        assert!(transient("HTTP/1.1 504"));
+
+        assert!(!transient("status 404"));
+        assert!(!transient("code 400"));
+    }
+
+    #[test]
+    fn bare_number_is_not_status_code() {
+        assert!(!transient("not found gallery 503"));
+        assert!(!transient("id=500;page=502"));
+        assert!(!transient("page 1 504"));
+    }
+
+    #[test]
+    fn messages() {
+        assert!(transient("operation timed out"));
+        assert!(transient("error trying to connect: dns error"));
+        assert!(transient("503 Service Unavailable"));
+
+        assert!(!transient("invalid json"));
+    }
+
+    #[test]
+    fn unauthorized() {
+        assert!(is_unauthorized(&Message("status 401")));
+        assert!(is_unauthorized(&Message("401 Unauthorized")));
+
+        assert!(!is_unauthorized(&Message("status 403")));
+        assert!(!is_unauthorized(&Message("gallery 401")));
+    }
+
+    #[test]
+    fn delay_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+
+        for _ in 0..100 {
+            assert!(policy.delay(1) <= policy.base_delay);
+            assert!(policy.delay(2) <= policy.base_delay * 2);
+
+            for attempt in 1..=64 {
+                assert!(policy.delay(attempt) <= policy.max_delay);
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! 디스크에 저장하는 상태 파일들 (token, job, error, cursor)
+//!
+//! 쓰는 도중에 꺼져도 이전 파일이나 새 파일 중 하나는 온전히 남도록
+//! 임시 파일에 쓰고 fsync한 다음에 rename합니다.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{
+    fs::{self, File},
+    io::AsyncWriteExt,
+};
+
+/// 임시 파일에 쓰고 fsync한 다음에 rename함
+pub async fn write(path: impl AsRef<Path>, buf: impl AsRef<[u8]>) -> io::Result<()> {
+    let path = path.as_ref();
+    let tmp = with_suffix(path, "tmp");
+
+    let mut file = File::create(&tmp).await?;
+
+    file.write_all(buf.as_ref()).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    fs::rename(&tmp, path).await?;
+
+    // rename도 디스크에 기록되도록 폴더도 fsync함
+    let dir = match path.parent() {
+        Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+        Some(dir) => dir,
+        None => Path::new("."),
+    };
+
+    File::open(dir).await?.sync_all().await
+}
+
+pub async fn write_json<T: Serialize>(path: impl AsRef<Path>, x: &T) -> io::Result<()> {
+    let serialized = serde_json::to_vec(x).unwrap();
+
+    write(path, serialized).await
+}
+
+/// 파일이 없다면 `None`
+///
+/// 파일이 올바르지 않다면 `io::ErrorKind::InvalidData`
+pub async fn read_json<T: DeserializeOwned>(path: impl AsRef<Path>) -> io::Result<Option<T>> {
+    let path = path.as_ref();
+
+    let buf = match fs::read(path).await {
+        Ok(buf) => buf,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    serde_json::from_slice(&buf).map(Some).map_err(|err| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("corrupted file {}: {err}", path.display()),
+        )
+    })
+}
+
+/// 올바르지 않은 파일을 `{path}.corrupted.{timestamp}`로 옮겨둠
+///
+/// 옮긴 파일의 경로를 반환함
+pub async fn quarantine(path: impl AsRef<Path>) -> io::Result<PathBuf> {
+    let path = path.as_ref();
+    let to = with_suffix(path, &format!("corrupted.{}", Utc::now().timestamp()));
+
+    fs::rename(path, &to).await?;
+
+    log::warn!(
+        "storage;quarantine;from={};to={}",
+        path.display(),
+        to.display()
+    );
+
+    Ok(to)
+}
+
+/// `{path}.{suffix}`
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut x = path.as_os_str().to_owned();
+
+    x.push(".");
+    x.push(suffix);
+
+    PathBuf::from(x)
+}